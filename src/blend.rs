@@ -1,12 +1,31 @@
-use soroban_sdk::{vec, Address, Env, Vec};
-use crate::storage::Config;
+use soroban_sdk::{
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    panic_with_error, vec, Address, Env, IntoVal, InvokeError, Map, Symbol, Vec,
+};
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::{errors::LeverageError, storage::Config};
 
-mod blend {
+#[allow(clippy::too_many_arguments)]
+mod pool {
     soroban_sdk::contractimport!(file = "./pool.wasm");
 }
 
-pub use blend::Client as PoolClient;
-pub use blend::{Request, Positions};
+pub use pool::Client as PoolClient;
+pub use pool::{FlashLoan, Request, Positions, Reserve};
+
+/// Blend `PoolError` codes the contract translates
+const POOL_BALANCE_ERROR: u32 = 10;
+const POOL_STATUS_NOT_ALLOWED: u32 = 1204;
+const POOL_INVALID_HF: u32 = 1205;
+const POOL_INVALID_POOL_STATUS: u32 = 1206;
+const POOL_INVALID_UTIL_RATE: u32 = 1207;
+const POOL_EXCEEDED_SUPPLY_CAP: u32 = 1220;
+const POOL_RESERVE_DISABLED: u32 = 1223;
+
+/// Fixed point scalar for Blend's c_factor and l_factor
+pub const SCALAR_7: i128 = 10_000_000;
+/// Fixed point scalar for Blend's b_rate and d_rate
+pub const SCALAR_12: i128 = 1_000_000_000_000;
 
 // Define the RequestType enum with explicit u32 values
 #[derive(Clone, PartialEq)]
@@ -18,6 +37,75 @@ pub enum RequestType {
     Repay = 5,
 }
 
+/// Collects requests against the contract's position so they go to the pool
+/// in one `submit`, with a single health check at the end
+pub struct RequestBuilder<'a> {
+    e: &'a Env,
+    config: &'a Config,
+    requests: Vec<Request>,
+    /// Amounts the pool pulls from this contract, by asset
+    transfers: Map<Address, i128>,
+}
+
+impl<'a> RequestBuilder<'a> {
+    pub fn new(e: &'a Env, config: &'a Config) -> Self {
+        Self {
+            e,
+            config,
+            requests: vec![e],
+            transfers: Map::new(e),
+        }
+    }
+
+    /// Supply `amount` of collateral from this contract
+    pub fn supply(self, amount: i128) -> Self {
+        let asset = self.config.collateral_asset.clone();
+        self.push(RequestType::SupplyCollateral, asset, amount)
+    }
+
+    /// Withdraw `amount` of collateral to the `to` of the submit
+    pub fn withdraw(self, amount: i128) -> Self {
+        let asset = self.config.collateral_asset.clone();
+        self.push(RequestType::WithdrawCollateral, asset, amount)
+    }
+
+    /// Borrow `amount` of debt to the `to` of the submit
+    pub fn borrow(self, amount: i128) -> Self {
+        let asset = self.config.debt_asset.clone();
+        self.push(RequestType::Borrow, asset, amount)
+    }
+
+    /// Repay `amount` of debt from this contract
+    pub fn repay(self, amount: i128) -> Self {
+        let asset = self.config.debt_asset.clone();
+        self.push(RequestType::Repay, asset, amount)
+    }
+
+    /// Submits the collected requests for `from`, sending withdrawals and
+    /// borrows to `to`
+    pub fn submit(self, from: &Address, to: &Address) -> Positions {
+        if !self.transfers.is_empty() {
+            authorize_pool_transfers(self.e, self.config, &self.transfers);
+        }
+        let pool_client = PoolClient::new(self.e, &self.config.blend_pool);
+        submit(self.e, &pool_client, from, to, &self.requests)
+    }
+
+    fn push(mut self, request_type: RequestType, asset: Address, amount: i128) -> Self {
+        require_nonzero(self.e, amount);
+        if matches!(request_type, RequestType::SupplyCollateral | RequestType::Repay) {
+            let transfer = self.transfers.get(asset.clone()).unwrap_or(0);
+            self.transfers.set(asset.clone(), transfer + amount);
+        }
+        self.requests.push_back(Request {
+            request_type: request_type as u32,
+            address: asset,
+            amount,
+        });
+        self
+    }
+}
+
 /// Deposit collateral to Blend pool
 pub fn deposit(
     e: &Env,
@@ -25,14 +113,7 @@ pub fn deposit(
     from: &Address,
     amount: i128,
 ) -> Positions {
-    let pool_client = PoolClient::new(e, &config.blend_pool);
-    let request = Request {
-        request_type: RequestType::SupplyCollateral as u32,
-        address: config.collateral_asset.clone(),
-        amount,
-    };
-
-    pool_client.submit(from, from, from, &vec![e, request])
+    RequestBuilder::new(e, config).supply(amount).submit(from, from)
 }
 
 /// Withdraw collateral from Blend pool
@@ -43,49 +124,66 @@ pub fn withdraw(
     to: &Address,
     amount: i128,
 ) -> Positions {
-    let pool_client = PoolClient::new(e, &config.blend_pool);
-    let request = Request {
-        request_type: RequestType::WithdrawCollateral as u32,
-        address: config.collateral_asset.clone(),
-        amount,
-    };
-
-    pool_client.submit(from, from, to, &vec![e, request])
+    RequestBuilder::new(e, config).withdraw(amount).submit(from, to)
 }
 
-/// Borrow debt asset from Blend pool
-pub fn borrow(
+/// Repay debt to Blend pool
+pub fn repay(
     e: &Env,
     config: &Config,
     from: &Address,
-    to: &Address,
     amount: i128,
 ) -> Positions {
-    let pool_client = PoolClient::new(e, &config.blend_pool);
-    let request = Request {
-        request_type: RequestType::Borrow as u32,
-        address: config.debt_asset.clone(),
-        amount,
-    };
-
-    pool_client.submit(from, from, to, &vec![e, request])
+    RequestBuilder::new(e, config).repay(amount).submit(from, from)
 }
 
-/// Repay debt to Blend pool
-pub fn repay(
+/// Get the reserve (config and rate data) for an asset
+pub fn get_reserve(
     e: &Env,
     config: &Config,
-    from: &Address,
-    amount: i128,
-) -> Positions {
+    asset: &Address,
+) -> Reserve {
     let pool_client = PoolClient::new(e, &config.blend_pool);
-    let request = Request {
-        request_type: RequestType::Repay as u32,
-        address: config.debt_asset.clone(),
-        amount,
-    };
+    pool_client.get_reserve(asset)
+}
 
-    pool_client.submit(from, from, from, &vec![e, request])
+/// Collateral held for a reserve, converted from bTokens to underlying
+pub fn collateral_balance(
+    e: &Env,
+    positions: &Positions,
+    reserve: &Reserve,
+) -> i128 {
+    let b_tokens = positions.collateral.get(reserve.config.index).unwrap_or(0);
+    b_tokens.fixed_mul_floor(e, &reserve.data.b_rate, &SCALAR_12)
+}
+
+/// Liabilities held for a reserve, converted from dTokens to underlying
+pub fn debt_balance(
+    e: &Env,
+    positions: &Positions,
+    reserve: &Reserve,
+) -> i128 {
+    let d_tokens = positions.liabilities.get(reserve.config.index).unwrap_or(0);
+    d_tokens.fixed_mul_ceil(e, &reserve.data.d_rate, &SCALAR_12)
+}
+
+/// Lowest collateral-to-debt ratio (in bps) Blend accepts for this pair of
+/// reserves, i.e. the ratio at which the health factor reaches 1.
+///
+/// Returns `None` if the collateral reserve cannot back a borrow or the debt
+/// reserve cannot be borrowed.
+pub fn min_c_factor(
+    e: &Env,
+    collateral_reserve: &Reserve,
+    debt_reserve: &Reserve,
+) -> Option<i128> {
+    let c_factor = collateral_reserve.config.c_factor as i128;
+    let l_factor = debt_reserve.config.l_factor as i128;
+    if c_factor == 0 || l_factor == 0 {
+        return None;
+    }
+
+    Some((10_000 * SCALAR_7).fixed_div_ceil(e, &(c_factor * l_factor), &SCALAR_7))
 }
 
 /// Get positions for an address
@@ -98,6 +196,22 @@ pub fn get_positions(
     pool_client.get_positions(address)
 }
 
+/// Blend reserve token ids the position earns emissions on: the bToken of
+/// the collateral reserve and the dToken of the debt reserve
+pub fn reserve_token_ids(e: &Env, config: &Config) -> Vec<u32> {
+    let pool_client = PoolClient::new(e, &config.blend_pool);
+    let reserves = pool_client.get_reserve_list();
+    let index_of = |asset: &Address| match reserves.first_index_of(asset) {
+        Some(index) => index,
+        None => panic_with_error!(e, LeverageError::UnsupportedToken),
+    };
+    vec![
+        e,
+        index_of(&config.collateral_asset) * 2 + 1,
+        index_of(&config.debt_asset) * 2,
+    ]
+}
+
 /// Claim rewards from the pool
 pub fn claim(
     e: &Env,
@@ -107,5 +221,74 @@ pub fn claim(
     to: &Address,
 ) -> i128 {
     let pool_client = PoolClient::new(e, &config.blend_pool);
-    pool_client.claim(from, reserve_token_ids, to)
-}
\ No newline at end of file
+    match pool_client.try_claim(from, reserve_token_ids, to) {
+        Ok(Ok(claimed)) => claimed,
+        Err(error) => panic_with_error!(e, pool_error(error)),
+        Ok(Err(_)) => panic_with_error!(e, LeverageError::PoolRejected),
+    }
+}
+
+/// Submits `requests` for `from`, translating a Blend rejection into the
+/// matching `LeverageError`
+fn submit(
+    e: &Env,
+    pool_client: &PoolClient,
+    from: &Address,
+    to: &Address,
+    requests: &Vec<Request>,
+) -> Positions {
+    match pool_client.try_submit(from, from, to, requests) {
+        Ok(Ok(positions)) => positions,
+        Err(error) => panic_with_error!(e, pool_error(error)),
+        Ok(Err(_)) => panic_with_error!(e, LeverageError::PoolRejected),
+    }
+}
+
+/// Maps a failed pool call onto the `LeverageError` describing it
+fn pool_error(error: Result<soroban_sdk::Error, InvokeError>) -> LeverageError {
+    let code = match error {
+        Ok(error) => error.get_code(),
+        Err(InvokeError::Contract(code)) => code,
+        Err(InvokeError::Abort) => return LeverageError::PoolRejected,
+    };
+    match code {
+        POOL_STATUS_NOT_ALLOWED | POOL_INVALID_POOL_STATUS | POOL_RESERVE_DISABLED => {
+            LeverageError::PoolFrozen
+        }
+        POOL_BALANCE_ERROR | POOL_INVALID_UTIL_RATE => LeverageError::InsufficientLiquidity,
+        POOL_INVALID_HF => LeverageError::UnhealthyPosition,
+        POOL_EXCEEDED_SUPPLY_CAP => LeverageError::SupplyCapExceeded,
+        _ => LeverageError::PoolRejected,
+    }
+}
+
+fn require_nonzero(e: &Env, amount: i128) {
+    if amount <= 0 {
+        panic_with_error!(e, LeverageError::ZeroBalance);
+    }
+}
+
+/// Authorizes the pool to pull each asset's amount in `transfers` from this
+/// contract during the next submit
+fn authorize_pool_transfers(
+    e: &Env,
+    config: &Config,
+    transfers: &Map<Address, i128>,
+) {
+    let mut entries = vec![e];
+    for (asset, amount) in transfers.iter() {
+        entries.push_back(InvokerContractAuthEntry::Contract(SubContractInvocation {
+            context: ContractContext {
+                contract: asset,
+                fn_name: Symbol::new(e, "transfer"),
+                args: (
+                    e.current_contract_address(),
+                    config.blend_pool.clone(),
+                    amount,
+                ).into_val(e),
+            },
+            sub_invocations: vec![e],
+        }));
+    }
+    e.authorize_as_current_contract(entries);
+}
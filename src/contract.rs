@@ -1,40 +1,362 @@
-use soroban_sdk::{contract, contractimpl, Address, Env, token, vec, Vec, panic_with_error};
+use soroban_sdk::{
+    auth::{Context, CustomAccountInterface},
+    contract, contractimpl, crypto::Hash, symbol_short, Address, BytesN, Env, Symbol, token,
+    panic_with_error, TryIntoVal, vec, Vec,
+};
+use soroban_fixed_point_math::SorobanFixedPoint;
 use crate::{
-    blend,
-    swap,
+    blend::{self, Positions, Request, RequestBuilder, RequestType},
+    events::{self, DeleverageEvent, LeverageUpEvent},
+    oracle,
+    position::{self, PositionInfo},
+    swap::{self, SwapLimits},
     errors::LeverageError,
-    storage::{Config, set_config, get_config},
+    storage::{
+        Config, FlashLoan, Venue, SCHEMA_VERSION, set_config, get_config, set_pending_owner, get_pending_owner,
+        remove_pending_owner, set_schema_version, set_guardian, get_guardian, set_paused,
+        is_paused, set_flash_lender, is_flash_lender, set_flash_loan, take_flash_loan,
+        set_claim_ids, get_claim_ids, set_lever_path, set_delever_routes, set_max_price_impact,
+        set_compound_path, remove_compound_path, set_max_price_age, set_venue,
+    },
 };
 
+/// Version of this contract's code, bumped with every release
+const VERSION: u32 = 2;
+/// Basis points scalar used for c-factors
+pub(crate) const BPS: i128 = 10_000;
+/// Most borrow, swap and supply rounds a single call will run
+const MAX_ROUNDS: u32 = 8;
+/// Margin kept above Blend's liquidation c-factor between rounds, in bps
+const ROUND_BUFFER: i128 = 500;
+
 #[contract]
 pub struct LeverageContract;
 
 #[contractimpl]
 impl LeverageContract {
     /// Initializes the leverage contract
-    pub fn __constructor(
-        env: Env,
-        owner: Address,
-        blend_pool: Address,
-        collateral_asset: Address,
-        debt_asset: Address,
-        reward_token: Address,
-        swap_router: Address,
-        target_c_factor: i128,
-    ) {
-        let config = Config {
-            owner,
-            blend_pool,
-            collateral_asset,
-            debt_asset,
-            reward_token,
-            swap_router,
-            target_c_factor,
+    pub fn __constructor(env: Env, config: Config) {
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        set_schema_version(&env, SCHEMA_VERSION);
+    }
+
+    /// Version of the deployed code
+    pub fn version() -> u32 {
+        VERSION
+    }
+
+    /// Replaces the contract's code while keeping its storage and Blend
+    /// position. Stored data is migrated on the first call to the new code.
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+        events::upgraded(&env, &new_wasm_hash);
+    }
+
+    /// Points swaps at a new Soroswap router
+    pub fn set_swap_router(env: Env, swap_router: Address) {
+        let mut config = get_config(&env);
+        config.owner.require_auth();
+
+        config.swap_router = swap_router.clone();
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        events::config_updated(&env, symbol_short!("router"), swap_router);
+    }
+
+    /// Picks the DEX every swap goes through: Soroswap's router or its
+    /// aggregator, Phoenix's pools, Aqua's pools or a Comet pool. Paths are
+    /// kept, so they have to be tradable on the new venue.
+    pub fn set_venue(env: Env, venue: Venue) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        let current_contract = env.current_contract_address();
+        match &venue {
+            Venue::Phoenix(address)
+            | Venue::Comet(address)
+            | Venue::SoroswapAggregator(address)
+            | Venue::Aqua(address)
+                if *address == current_contract =>
+            {
+                panic_with_error!(&env, LeverageError::InvalidConfig)
+            }
+            _ => {}
+        }
+
+        set_venue(&env, &venue);
+        events::config_updated(&env, symbol_short!("venue"), venue);
+    }
+
+    /// Sets the paths the router swaps along, debt to collateral when levering
+    /// up and collateral to debt when deleveraging, e.g. through XLM when the
+    /// pair has no deep direct pool. The delever path replaces any delever
+    /// routes.
+    pub fn set_swap_paths(env: Env, lever_path: Vec<Address>, delever_path: Vec<Address>) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        if !Self::is_valid_path(&lever_path, &config.debt_asset, &config.collateral_asset)
+            || !Self::is_valid_path(&delever_path, &config.collateral_asset, &config.debt_asset)
+        {
+            panic_with_error!(&env, LeverageError::InvalidConfig);
+        }
+
+        set_lever_path(&env, &lever_path);
+        set_delever_routes(&env, &vec![&env, delever_path.clone()]);
+        events::config_updated(&env, symbol_short!("paths"), (lever_path, delever_path));
+    }
+
+    /// Sets the paths collateral is sold along when deleveraging, replacing
+    /// the delever path. With more than one, each sale is split between them
+    /// to limit price impact, e.g. across a direct pair and a route through
+    /// XLM.
+    pub fn set_delever_routes(env: Env, routes: Vec<Vec<Address>>) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        if routes.is_empty()
+            || !routes.iter().all(|path| {
+                Self::is_valid_path(&path, &config.collateral_asset, &config.debt_asset)
+            })
+        {
+            panic_with_error!(&env, LeverageError::InvalidConfig);
+        }
+
+        set_delever_routes(&env, &routes);
+        events::config_updated(&env, symbol_short!("routes"), routes);
+    }
+
+    /// Sets the path `harvest_and_compound` sells rewards along for
+    /// collateral, e.g. BLND through USDC when there is no direct pair
+    pub fn set_compound_path(env: Env, path: Vec<Address>) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        if !Self::is_valid_path(&path, &config.reward_token, &config.collateral_asset) {
+            panic_with_error!(&env, LeverageError::InvalidConfig);
+        }
+
+        set_compound_path(&env, &path);
+        events::config_updated(&env, symbol_short!("compound"), path);
+    }
+
+    /// Sets the largest price impact (bps) a swap may have on the pairs it
+    /// trades through, worked out from their reserves
+    ///
+    /// Positions are not opened or closed into thin liquidity: the rounds that
+    /// lever a position up or unwind it each swap only what stays within it
+    /// and leave the rest to later rounds. Flash loan
+    /// swaps past it fail with `PriceImpactExceeded`, so keepers split those
+    /// into smaller loans.
+    pub fn set_max_price_impact(env: Env, max_price_impact_bps: i128) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        if !(0..=BPS).contains(&max_price_impact_bps) {
+            panic_with_error!(&env, LeverageError::InvalidConfig);
+        }
+
+        set_max_price_impact(&env, max_price_impact_bps);
+        events::config_updated(&env, symbol_short!("impact"), max_price_impact_bps);
+    }
+
+    /// Sets the oldest oracle price, in seconds, the contract sizes positions
+    /// and guards swaps with. Older prices fail with `StalePrice`.
+    pub fn set_max_price_age(env: Env, max_price_age: u64) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        if max_price_age == 0 {
+            panic_with_error!(&env, LeverageError::InvalidConfig);
+        }
+
+        set_max_price_age(&env, max_price_age);
+        events::config_updated(&env, symbol_short!("price_age"), max_price_age);
+    }
+
+    /// Sets the c-factor (bps) the position is steered to, which has to stay
+    /// inside the rebalance band
+    pub fn set_target_c_factor(env: Env, target_c_factor: i128) {
+        let mut config = get_config(&env);
+        config.owner.require_auth();
+
+        config.target_c_factor = target_c_factor;
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        events::config_updated(&env, symbol_short!("target"), target_c_factor);
+    }
+
+    /// Sets the c-factor band (bps) outside of which `rebalance` acts, which
+    /// has to keep `target_c_factor` inside it
+    pub fn set_c_factor_band(env: Env, min_c_factor: i128, max_c_factor: i128) {
+        let mut config = get_config(&env);
+        config.owner.require_auth();
+
+        config.min_c_factor = min_c_factor;
+        config.max_c_factor = max_c_factor;
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        events::config_updated(&env, symbol_short!("band"), (min_c_factor, max_c_factor));
+    }
+
+    /// Sets the token Blend pays rewards in. A compound path set for the old
+    /// token is dropped.
+    pub fn set_reward_token(env: Env, reward_token: Address) {
+        let mut config = get_config(&env);
+        config.owner.require_auth();
+
+        config.reward_token = reward_token.clone();
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        remove_compound_path(&env);
+        events::config_updated(&env, symbol_short!("reward"), reward_token);
+    }
+
+    /// Sets the default and the largest slippage (bps) a call may swap with
+    pub fn set_slippage_limits(env: Env, default_slippage_bps: i128, max_slippage_bps: i128) {
+        let mut config = get_config(&env);
+        config.owner.require_auth();
+
+        config.default_slippage_bps = default_slippage_bps;
+        config.max_slippage_bps = max_slippage_bps;
+        Self::require_valid_config(&env, &config);
+        set_config(&env, &config);
+        events::config_updated(
+            &env,
+            symbol_short!("slippage"),
+            (default_slippage_bps, max_slippage_bps),
+        );
+    }
+
+    /// Starts handing the contract over to `new_owner`, who has to call
+    /// `accept_owner` to take it. A later proposal replaces this one.
+    pub fn propose_owner(env: Env, new_owner: Address) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        set_pending_owner(&env, &new_owner);
+        events::owner_proposed(&env, &new_owner);
+    }
+
+    /// Completes a handover started with `propose_owner`
+    pub fn accept_owner(env: Env) {
+        let new_owner = match get_pending_owner(&env) {
+            Some(new_owner) => new_owner,
+            None => panic_with_error!(&env, LeverageError::BadRequest),
         };
+        new_owner.require_auth();
+
+        let mut config = get_config(&env);
+        let old_owner = config.owner;
+        config.owner = new_owner.clone();
         set_config(&env, &config);
+        remove_pending_owner(&env);
+        events::owner_accepted(&env, &old_owner, &new_owner);
+    }
+
+    /// Sets the Blend reserve token ids `claim` collects emissions for, in
+    /// place of the ones derived from the pool's reserve list
+    pub fn set_claim_ids(env: Env, ids: Vec<u32>) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        set_claim_ids(&env, &ids);
+        events::config_updated(&env, symbol_short!("claim_ids"), ids);
+    }
+
+    /// Sets the address allowed to pause the contract alongside the owner
+    pub fn set_guardian(env: Env, guardian: Address) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        set_guardian(&env, &guardian);
+        events::config_updated(&env, symbol_short!("guardian"), guardian);
+    }
+
+    /// Stops anything that adds risk: levering up through `exec_op`,
+    /// `open_position`, `rebalance` above the band and `claim`. Deleveraging
+    /// and closing stay open so the position can always be wound down.
+    ///
+    /// `caller` has to be the guardian or the owner.
+    pub fn pause(env: Env, caller: Address) {
+        let config = get_config(&env);
+        if caller != config.owner && Some(caller.clone()) != get_guardian(&env) {
+            panic_with_error!(&env, LeverageError::Unauthorized);
+        }
+        caller.require_auth();
+
+        set_paused(&env, true);
+        events::paused(&env, &caller);
+    }
+
+    /// Lifts a pause. Only the owner can unpause.
+    pub fn unpause(env: Env) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        set_paused(&env, false);
+        events::unpaused(&env, &config.owner);
+    }
+
+    /// Whether the contract is paused
+    pub fn paused(env: Env) -> bool {
+        is_paused(&env)
+    }
+
+    /// Allows or disallows `lender` to call `exec_op`. The Blend pool is
+    /// always allowed.
+    pub fn set_flash_lender(env: Env, lender: Address, allowed: bool) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        set_flash_lender(&env, &lender, allowed);
+        events::config_updated(&env, symbol_short!("lender"), (lender, allowed));
+    }
+
+    /// Starts a flash loan of `amount` of `token` from `lender`, which the
+    /// lender's call to `exec_op` has to match
+    ///
+    /// Soroban does not let a contract be re-entered, so this contract cannot
+    /// request the loan itself and stay on the stack for `exec_op`. The owner
+    /// starts it here instead and requests it from the lender afterwards,
+    /// before the temporary entry expires. A new loan replaces one not yet
+    /// taken.
+    pub fn begin_flash_loan(env: Env, lender: Address, token: Address, amount: i128) {
+        let config = get_config(&env);
+        config.owner.require_auth();
+        if amount <= 0 {
+            panic_with_error!(&env, LeverageError::ZeroBalance);
+        }
+        if !Self::is_allowed_lender(&env, &config, &lender) {
+            panic_with_error!(&env, LeverageError::Unauthorized);
+        }
+        if token != config.collateral_asset && token != config.debt_asset {
+            panic_with_error!(&env, LeverageError::UnsupportedToken);
+        }
+
+        set_flash_loan(&env, &FlashLoan {
+            lender,
+            token,
+            amount,
+            supply: 0,
+        });
     }
 
     /// Flash loan receiver - exact signature as required
+    ///
+    /// Only runs for a flash loan this contract is waiting for: a Blend
+    /// `flash_loan` on its own position it authorized in `__check_auth`, or
+    /// one started with `begin_flash_loan`, called by that lender with the
+    /// same token and amount.
+    ///
+    /// Blend names the borrower, this contract, as `caller` and has already
+    /// booked the loan as debt, so all that is left is selling it for the
+    /// collateral its requests supply afterwards. Other lenders are paid back
+    /// here.
     pub fn exec_op(
         env: Env,
         caller: Address,
@@ -42,31 +364,91 @@ impl LeverageContract {
         amount: i128,
         fee: i128,
     ) {
-        caller.require_auth();
+        let current_contract = env.current_contract_address();
+        let blend_flash_loan = caller == current_contract;
+        if !blend_flash_loan {
+            caller.require_auth();
+        }
+        if amount <= 0 {
+            panic_with_error!(&env, LeverageError::ZeroBalance);
+        }
         let config = get_config(&env);
 
         // Ensure the owner authorizes this operation
         config.owner.require_auth();
-        let current_contract = env.current_contract_address();
+
+        // Only the flash loan this contract is waiting for, once
+        let lender = if blend_flash_loan {
+            config.blend_pool.clone()
+        } else {
+            caller.clone()
+        };
+        let flash_loan = match take_flash_loan(&env) {
+            Some(flash_loan)
+                if flash_loan.lender == lender
+                    && flash_loan.token == token
+                    && flash_loan.amount == amount
+                    && Self::is_allowed_lender(&env, &config, &lender) => flash_loan,
+            _ => panic_with_error!(&env, LeverageError::Unauthorized),
+        };
+
+        let limits = Self::swap_limits(&env, &config, None, None);
+        if blend_flash_loan {
+            Self::require_not_paused(&env);
+            let mut event = LeverageUpEvent {
+                flash_amount: amount,
+                supplied: flash_loan.supply,
+                borrowed: amount,
+                ..Default::default()
+            };
+            Self::handle_blend_leverage_up(
+                &env,
+                &config,
+                amount,
+                flash_loan.supply,
+                &limits,
+                &mut event,
+            );
+            events::blend_leverage_up(&env, event);
+            return;
+        }
 
         if token == config.collateral_asset {
             // LEVERAGE UP: Received collateral via flash loan
-            Self::handle_leverage_up(
+            Self::require_not_paused(&env);
+            let mut event = LeverageUpEvent {
+                flash_amount: amount,
+                fee,
+                ..Default::default()
+            };
+            let positions = Self::handle_leverage_up(
                 &env,
                 &config,
                 amount,
                 fee,
+                &limits,
+                &mut event,
             );
+            Self::require_healthy(&env, &config);
+            events::leverage_up(&env, &config, event, &positions);
         } else if token == config.debt_asset {
             // DELEVERAGE: Received debt token via flash loan
-            Self::handle_deleverage(
+            let mut event = DeleverageEvent {
+                flash_amount: amount,
+                fee,
+                ..Default::default()
+            };
+            let positions = Self::handle_deleverage(
                 &env,
                 &config,
                 amount,
                 fee,
+                &limits,
+                &mut event,
             );
+            events::deleverage(&env, &config, event, &positions);
         } else {
-            panic_with_error!(&env, LeverageError::BadRequest);
+            panic_with_error!(&env, LeverageError::UnsupportedToken);
         }
 
         // Send tokens back to repay flash loan
@@ -75,9 +457,232 @@ impl LeverageContract {
         token_client.transfer(&current_contract, &caller, &repay_amount);
     }
 
+    /// Opens or adds to the leveraged position in a single call
+    ///
+    /// Pulls `collateral_in` from the owner and borrows enough debt to take it
+    /// to `target_leverage` (total collateral value / equity, in bps), priced
+    /// with the oracle. Soroban does not allow a flash loan started here to
+    /// call back into `exec_op`, so the borrow is built up in rounds: each one
+    /// borrows what the collateral supports just above Blend's liquidation
+    /// line, and no more than the price impact limit lets it swap, swaps it
+    /// and supplies the result. Every swap must come within
+    /// `max_slippage_bps` of the oracle price (the configured default if
+    /// `None`) and before `deadline`.
+    ///
+    /// With rounds borrowing down to a c-factor `c`, `MAX_ROUNDS` of them
+    /// reach leverage of at most `(1 - r^(MAX_ROUNDS + 1)) / (1 - r)` for
+    /// `r = 1 / c`, less swap costs: about 4.6x when Blend liquidates at
+    /// 117%. Higher targets fail up front with `LeverageTooHigh`.
+    pub fn open_position(
+        env: Env,
+        collateral_in: i128,
+        target_leverage: i128,
+        max_slippage_bps: Option<i128>,
+        deadline: Option<u64>,
+    ) -> Positions {
+        let config = get_config(&env);
+        config.owner.require_auth();
+        Self::require_not_paused(&env);
+        if collateral_in <= 0 || target_leverage <= BPS {
+            panic_with_error!(&env, LeverageError::BadRequest);
+        }
+        let limits = Self::swap_limits(&env, &config, max_slippage_bps, deadline);
+
+        let current_contract = env.current_contract_address();
+        token::Client::new(&env, &config.collateral_asset).transfer(
+            &config.owner,
+            &current_contract,
+            &collateral_in,
+        );
+
+        let collateral_reserve = blend::get_reserve(&env, &config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(&env, &config, &config.debt_asset);
+        let collateral_price = oracle::get_price(&env, &config, &config.collateral_asset);
+        let debt_price = oracle::get_price(&env, &config, &config.debt_asset);
+
+        // Debt worth (L - 1) times the new collateral
+        let total_borrow = collateral_in
+            .fixed_mul_floor(&env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_floor(&env, &(target_leverage - BPS), &BPS)
+            .fixed_mul_floor(&env, &debt_reserve.scalar, &debt_price);
+
+        // Leverage L is the c-factor L / (L - 1)
+        let target_c_factor = target_leverage.fixed_mul_floor(&env, &BPS, &(target_leverage - BPS));
+        let mut event = LeverageUpEvent {
+            supplied: collateral_in,
+            ..Default::default()
+        };
+        let positions = Self::lever_up(
+            &env,
+            &config,
+            collateral_in,
+            target_c_factor,
+            total_borrow,
+            &limits,
+            &mut event,
+        );
+        Self::require_healthy(&env, &config);
+        events::leverage_up(&env, &config, event, &positions);
+        positions
+    }
+
+    /// Fully unwinds the position and sends the remaining collateral to the
+    /// owner
+    ///
+    /// Pays off the outstanding debt with the position's own collateral, the
+    /// last round only swapping enough to clear what is left. Fails if the
+    /// owner would receive less than `min_collateral_out`. Swap limits work as
+    /// in `open_position`.
+    ///
+    /// Near liquidation there is too little room to withdraw collateral in
+    /// rounds, so the owner has to hold the debt: it is repaid in one go and
+    /// paid back from the swapped collateral within the call.
+    pub fn close_position(
+        env: Env,
+        min_collateral_out: i128,
+        max_slippage_bps: Option<i128>,
+        deadline: Option<u64>,
+    ) -> i128 {
+        let config = get_config(&env);
+        config.owner.require_auth();
+        let limits = Self::swap_limits(&env, &config, max_slippage_bps, deadline);
+
+        let mut event = DeleverageEvent::default();
+        let mut positions = Self::unwind(&env, &config, None, &limits, &mut event);
+
+        let collateral_reserve = blend::get_reserve(&env, &config, &config.collateral_asset);
+        let collateral = blend::collateral_balance(&env, &positions, &collateral_reserve);
+        if collateral < min_collateral_out {
+            panic_with_error!(&env, LeverageError::InsufficientSwapOutput);
+        }
+        let current_contract = env.current_contract_address();
+        if collateral > 0 {
+            // One over the balance also clears any bToken dust
+            positions = blend::withdraw(
+                &env,
+                &config,
+                &current_contract,
+                &config.owner,
+                collateral + 1,
+            );
+            event.withdrawn += collateral;
+        }
+        events::deleverage(&env, &config, event, &positions);
+
+        // Send the owner any debt left over, such as an overshoot Blend
+        // refunded on the final repay
+        let debt_client = token::Client::new(&env, &config.debt_asset);
+        let debt_left = debt_client.balance(&current_contract);
+        if debt_left > 0 {
+            debt_client.transfer(&current_contract, &config.owner, &debt_left);
+        }
+
+        collateral
+    }
+
+    /// Steps the position down to `target_c_factor_override` (collateral value
+    /// / debt value, in bps) by repaying debt with its own collateral
+    ///
+    /// The override has to be above the position's current c-factor. Swap
+    /// limits work as in `open_position`, and near liquidation the owner
+    /// fronts the repayment as in `close_position`.
+    pub fn reduce_leverage(
+        env: Env,
+        target_c_factor_override: i128,
+        max_slippage_bps: Option<i128>,
+        deadline: Option<u64>,
+    ) -> Positions {
+        let config = get_config(&env);
+        config.owner.require_auth();
+        let limits = Self::swap_limits(&env, &config, max_slippage_bps, deadline);
+
+        let positions = blend::get_positions(&env, &config, &env.current_contract_address());
+        let repay_amount = Self::calculate_repay_amount(
+            &env,
+            &config,
+            &positions,
+            0,
+            target_c_factor_override,
+        );
+        if repay_amount <= 0 {
+            // Already at or below the requested leverage
+            panic_with_error!(&env, LeverageError::AlreadyAtTarget);
+        }
+
+        let mut event = DeleverageEvent::default();
+        let positions = Self::unwind(
+            &env,
+            &config,
+            Some(target_c_factor_override),
+            &limits,
+            &mut event,
+        );
+        events::deleverage(&env, &config, event, &positions);
+        positions
+    }
+
+    /// Brings the position back to `target_c_factor` once it has drifted
+    /// outside the `min_c_factor` to `max_c_factor` band
+    ///
+    /// Anyone can call this, so keepers can look after the position without
+    /// the owner's key. Above the band it levers up in rounds like
+    /// `open_position`. Below it the position is too close to liquidation to
+    /// unwind from its own collateral, so `keeper` fronts the debt to repay and
+    /// gets it back from the swapped collateral within the call. Swaps are held
+    /// to the configured default slippage, which keepers cannot widen.
+    pub fn rebalance(env: Env, keeper: Address) -> Positions {
+        let config = get_config(&env);
+        let limits = Self::swap_limits(&env, &config, None, None);
+        let info = position::get_position(&env, &config);
+        if info.debt == 0 {
+            panic_with_error!(&env, LeverageError::ZeroBalance);
+        }
+
+        if info.c_factor < config.min_c_factor {
+            keeper.require_auth();
+            let mut event = DeleverageEvent::default();
+            let positions = Self::delever_fronted(
+                &env,
+                &config,
+                &keeper,
+                Some(config.target_c_factor),
+                &limits,
+                &mut event,
+            );
+            events::deleverage(&env, &config, event, &positions);
+            positions
+        } else if info.c_factor > config.max_c_factor {
+            Self::require_not_paused(&env);
+            let borrow_amount = Self::calculate_lever_up_amount(&env, &config, &info);
+            let mut event = LeverageUpEvent::default();
+            let positions = Self::lever_up(
+                &env,
+                &config,
+                0,
+                config.target_c_factor,
+                borrow_amount,
+                &limits,
+                &mut event,
+            );
+            Self::require_healthy(&env, &config);
+            events::leverage_up(&env, &config, event, &positions);
+            positions
+        } else {
+            panic_with_error!(&env, LeverageError::AlreadyAtTarget);
+        }
+    }
+
+    /// Current collateral, debt and risk ratios of the position
+    pub fn get_position(env: Env) -> PositionInfo {
+        position::get_position(&env, &get_config(&env))
+    }
+
     /// Claims rewards from Blend (similar to harvest in blend strategy)
     pub fn claim(env: Env, from: Address) -> Result<(), LeverageError> {
         from.require_auth();
+        if is_paused(&env) {
+            return Err(LeverageError::Paused);
+        }
 
         let config = get_config(&env);
         config.owner.require_auth();
@@ -85,149 +690,1082 @@ impl LeverageContract {
         let current_contract = env.current_contract_address();
 
         // Claim rewards
-        let rewards_claimed = blend::claim(
-            &env,
-            &config,
-            &current_contract,
-            &vec![&env, 0u32, 1u32],
-            &current_contract
-        );
+        let rewards_claimed = Self::claim_rewards(&env, &config);
 
         if rewards_claimed > 0 {
             // Transfer rewards to the caller
             let reward_client = token::Client::new(&env, &config.reward_token);
             reward_client.transfer(&current_contract, &from, &rewards_claimed);
+            events::claim(&env, &from, rewards_claimed);
         }
 
         Ok(())
     }
 
+    /// Claims Blend rewards and compounds them into the position
+    ///
+    /// The claimed `reward_token` is sold along the compound path for at
+    /// least `min_out` collateral, and no less than the oracle price within
+    /// the default slippage, which is supplied to Blend. With `relever`, debt
+    /// is then borrowed back in rounds, like `rebalance`, until the position
+    /// sits at `target_c_factor` again.
+    pub fn harvest_and_compound(env: Env, min_out: i128, relever: bool) -> Positions {
+        let config = get_config(&env);
+        config.owner.require_auth();
+        Self::require_not_paused(&env);
+        let limits = Self::swap_limits(&env, &config, None, None);
+
+        let claimed = Self::claim_rewards(&env, &config);
+        if claimed <= 0 {
+            panic_with_error!(&env, LeverageError::ZeroBalance);
+        }
+
+        let current_contract = env.current_contract_address();
+        let path = swap::compound_path(&env, &config);
+        // Held to the oracle as well, so a skewed pair cannot take the rewards
+        // for whatever `min_out` the owner passed
+        let min_out = min_out.max(swap::oracle_min_amount_out(
+            &env,
+            &config,
+            &path,
+            claimed,
+            limits.max_slippage_bps,
+        ));
+        let amounts = swap::swap_exact_tokens_for_tokens(
+            &env,
+            &config,
+            claimed,
+            min_out,
+            path,
+            &current_contract,
+            limits.deadline,
+        );
+        let collateral_out = amounts.last().unwrap_or(0);
+        let mut positions = blend::deposit(&env, &config, &current_contract, collateral_out);
+        events::compound(&env, claimed, collateral_out);
+
+        if relever {
+            let info = position::get_position(&env, &config);
+            let borrow_amount = Self::calculate_lever_up_amount(&env, &config, &info);
+            if borrow_amount > 0 {
+                let mut event = LeverageUpEvent::default();
+                positions = Self::lever_up(
+                    &env,
+                    &config,
+                    0,
+                    config.target_c_factor,
+                    borrow_amount,
+                    &limits,
+                    &mut event,
+                );
+                Self::require_healthy(&env, &config);
+                events::leverage_up(&env, &config, event, &positions);
+            }
+        }
+
+        positions
+    }
+
     // Internal helper functions
 
+    fn is_allowed_lender(env: &Env, config: &Config, lender: &Address) -> bool {
+        *lender == config.blend_pool || is_flash_lender(env, lender)
+    }
+
+    /// Claims the position's Blend emissions into this contract
+    ///
+    /// Reserve token ids are derived from the pool's reserve list the first
+    /// time and kept, unless the owner has set them.
+    fn claim_rewards(env: &Env, config: &Config) -> i128 {
+        let ids = get_claim_ids(env).unwrap_or_else(|| {
+            let ids = blend::reserve_token_ids(env, config);
+            set_claim_ids(env, &ids);
+            ids
+        });
+        let current_contract = env.current_contract_address();
+        blend::claim(env, config, &current_contract, &ids, &current_contract)
+    }
+
+    /// Whether `path` swaps `from` into `to`
+    fn is_valid_path(path: &Vec<Address>, from: &Address, to: &Address) -> bool {
+        path.len() >= 2
+            && path.first().as_ref() == Some(from)
+            && path.last().as_ref() == Some(to)
+    }
+
+    fn require_not_paused(env: &Env) {
+        if is_paused(env) {
+            panic_with_error!(env, LeverageError::Paused);
+        }
+    }
+
+    /// Panics if levering up left the position below the `min_c_factor`
+    /// floor, where `rebalance` would immediately unwind it again
+    fn require_healthy(env: &Env, config: &Config) {
+        if position::get_position(env, config).c_factor < config.min_c_factor {
+            panic_with_error!(env, LeverageError::UnhealthyPosition);
+        }
+    }
+
+    /// Panics unless the c-factor band is ordered, the slippage limits are
+    /// below 100% with the default inside the maximum, the router is another
+    /// contract and rewards come in a token the position does not hold
+    fn require_valid_config(env: &Env, config: &Config) {
+        let current_contract = env.current_contract_address();
+        if config.min_c_factor > config.target_c_factor
+            || config.target_c_factor > config.max_c_factor
+            || !(0..BPS).contains(&config.max_slippage_bps)
+            || !(0..=config.max_slippage_bps).contains(&config.default_slippage_bps)
+            || config.swap_router == current_contract
+            || config.reward_token == current_contract
+            || config.reward_token == config.collateral_asset
+            || config.reward_token == config.debt_asset
+        {
+            panic_with_error!(env, LeverageError::InvalidConfig);
+        }
+    }
+
+    /// Resolves a call's swap limits, falling back to the configured default
+    /// slippage and a deadline of the next ledger second
+    fn swap_limits(
+        env: &Env,
+        config: &Config,
+        max_slippage_bps: Option<i128>,
+        deadline: Option<u64>,
+    ) -> SwapLimits {
+        let max_slippage_bps = max_slippage_bps.unwrap_or(config.default_slippage_bps);
+        if !(0..=config.max_slippage_bps).contains(&max_slippage_bps) {
+            panic_with_error!(env, LeverageError::InvalidConfig);
+        }
+
+        let now = env.ledger().timestamp();
+        let deadline = deadline.unwrap_or(now + 1);
+        if deadline < now {
+            panic_with_error!(env, LeverageError::DeadlineExpired);
+        }
+
+        SwapLimits {
+            max_slippage_bps,
+            deadline,
+        }
+    }
+
     fn handle_leverage_up(
         env: &Env,
         config: &Config,
         flash_amount: i128,
         fee: i128,
-    ) {
+        limits: &SwapLimits,
+        event: &mut LeverageUpEvent,
+    ) -> Positions {
         let current_contract = env.current_contract_address();
         let collateral_client = token::Client::new(env, &config.collateral_asset);
         // Get total collateral balance (user deposit + flash loan)
         let total_collateral = collateral_client.balance(&current_contract);
 
-        // Supply all collateral to Blend
-        let _ = blend::deposit(
+        // Now we need enough debt tokens to swap back into the flash loan repayment
+        let required_collateral = flash_amount + fee;
+
+        // Borrow up to the target c-factor once all collateral is supplied
+        let positions = blend::get_positions(env, config, &current_contract);
+        let max_borrow = Self::calculate_borrow_amount(
             env,
             config,
-            &current_contract,
+            &positions,
             total_collateral,
+            required_collateral,
         );
 
-        //TODO: Calculate how much to borrow based on target c-factor
-        let max_borrow = 0;
+        // Supply all collateral to Blend and borrow against it in one submit
+        let mut positions = RequestBuilder::new(env, config)
+            .supply(total_collateral)
+            .borrow(max_borrow)
+            .submit(&current_contract, &current_contract);
+        event.supplied += total_collateral;
+        event.borrowed += max_borrow;
 
-        // Borrow debt tokens from Blend
-        blend::borrow(
+        // Buy back exactly the flash loan repayment
+        let path = swap::lever_path(env, config);
+        let amounts = swap::swap_tokens_for_exact_tokens(
             env,
             config,
-            &current_contract,
-            &current_contract,
+            required_collateral,
             max_borrow,
+            path.clone(),
+            &current_contract,
+            limits.deadline,
         );
 
-        // Now we have debt tokens, need to swap to collateral tokens to repay flash loan
-        let required_collateral = flash_amount + fee;
-
-        // Swap debt tokens for collateral tokens to repay flash loan
-        let path = vec![env, config.debt_asset.clone(), config.collateral_asset.clone()];
-        let amounts = swap::swap_exact_tokens_for_tokens(
+        let debt_in = amounts.get(0).unwrap_or(0);
+        let bought = amounts.last().unwrap_or(0);
+        swap::require_oracle_price(
             env,
             config,
-            max_borrow,
-            required_collateral, // We need at least this amount
-            path,
-            &current_contract,
+            &path,
+            debt_in,
+            bought,
+            limits.max_slippage_bps,
         );
+        event.swap_in += debt_in;
+        event.swap_out += bought;
 
-        // Verify we got enough collateral
-        let collateral_received = amounts.get(1).unwrap_or(0);
-        if collateral_received < required_collateral {
-            panic_with_error!(env, LeverageError::BadRequest);
+        // The rest of the borrow goes back in as collateral, along with what
+        // venues emulating exact output bought past the repayment
+        let mut excess_collateral = bought - required_collateral;
+        let excess_debt = max_borrow - debt_in;
+        if excess_debt > 0 {
+            let min_out = swap::oracle_min_amount_out(
+                env,
+                config,
+                &path,
+                excess_debt,
+                limits.max_slippage_bps,
+            );
+            let amounts = swap::swap_exact_tokens_for_tokens(
+                env,
+                config,
+                excess_debt,
+                min_out,
+                path,
+                &current_contract,
+                limits.deadline,
+            );
+            let collateral_out = amounts.last().unwrap_or(0);
+            event.swap_in += excess_debt;
+            event.swap_out += collateral_out;
+            excess_collateral += collateral_out;
+        }
+        if excess_collateral > 0 {
+            positions = blend::deposit(
+                env,
+                config,
+                &current_contract,
+                excess_collateral,
+            );
+            event.supplied += excess_collateral;
         }
+
+        positions
     }
 
-    fn handle_deleverage(
+    /// Sells all the `flash_amount` of debt Blend lent for at least `supply`
+    /// collateral, for the pool to supply once `exec_op` returns
+    ///
+    /// The loan is already booked as debt, so none of it is left idle: the
+    /// whole amount is swapped, within the allowed slippage of the oracle
+    /// price, and collateral bought beyond `supply` goes to the owner. The
+    /// pool is mid-call, so nothing here may call back into it.
+    fn handle_blend_leverage_up(
         env: &Env,
         config: &Config,
         flash_amount: i128,
-        fee: i128,
+        supply: i128,
+        limits: &SwapLimits,
+        event: &mut LeverageUpEvent,
     ) {
         let current_contract = env.current_contract_address();
-        let collateral_client = token::Client::new(env, &config.collateral_asset);
-
-        // Repay debt with flash loaned tokens
-        let positions_after_repay = blend::repay(
+        let path = swap::lever_path(env, config);
+        let amounts = swap::swap_exact_tokens_for_tokens(
             env,
             config,
+            flash_amount,
+            supply,
+            path.clone(),
             &current_contract,
+            limits.deadline,
+        );
+        let collateral_out = amounts.last().unwrap_or(0);
+        swap::require_oracle_price(
+            env,
+            config,
+            &path,
             flash_amount,
+            collateral_out,
+            limits.max_slippage_bps,
         );
+        event.swap_in += flash_amount;
+        event.swap_out += collateral_out;
 
-        //TODO:
-        // Calculate remaining debt after repayment
-        let remaining_debt = positions_after_repay.debt.get(0).unwrap_or(0);
+        if collateral_out > supply {
+            token::Client::new(env, &config.collateral_asset).transfer(
+                &current_contract,
+                &config.owner,
+                &(collateral_out - supply),
+            );
+        }
+    }
 
-        let withdraw_amount = if remaining_debt == 0 {
-            // No debt left, withdraw all collateral
-            positions_after_repay.supply.get(0).unwrap_or(0)
-        } else {
-            // Calculate withdrawal to maintain target c-factor
-            let current_collateral = positions_after_repay.supply.get(0).unwrap_or(0);
-            let required_collateral = (remaining_debt * config.target_c_factor) / 10000;
+    /// Calculates how much debt to borrow so the position sits at
+    /// `target_c_factor` (collateral / debt, in bps) once it is drawn.
+    ///
+    /// Both assets are priced with the oracle, so a skewed pair cannot talk
+    /// the contract into a larger borrow, and the borrow has to at least cover
+    /// the oracle value of buying back `required_collateral`. What the buyback
+    /// really costs is held to the borrow and the oracle guard when it runs.
+    /// `supply` is collateral going in alongside the borrow.
+    fn calculate_borrow_amount(
+        env: &Env,
+        config: &Config,
+        positions: &Positions,
+        supply: i128,
+        required_collateral: i128,
+    ) -> i128 {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+
+        // The target has to stay above the ratio Blend would liquidate at
+        match blend::min_c_factor(env, &collateral_reserve, &debt_reserve) {
+            Some(min_c_factor) if config.target_c_factor > min_c_factor => {}
+            _ => panic_with_error!(env, LeverageError::TargetUnreachable),
+        }
+
+        let collateral = blend::collateral_balance(env, positions, &collateral_reserve) + supply;
+        let debt = blend::debt_balance(env, positions, &debt_reserve);
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
 
-            if current_collateral > required_collateral {
-                current_collateral - required_collateral
-            } else {
-                0
+        // Debt worth the flash loan repayment
+        let debt_for_repayment = required_collateral
+            .fixed_mul_ceil(env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_ceil(env, &debt_reserve.scalar, &debt_price);
+
+        // Total debt the collateral carries at the target
+        let target_debt = collateral
+            .fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_floor(env, &BPS, &config.target_c_factor)
+            .fixed_mul_floor(env, &debt_reserve.scalar, &debt_price);
+
+        let max_borrow = target_debt - debt;
+        if max_borrow < debt_for_repayment {
+            // Borrowing enough to repay the flash loan would push the
+            // position past the target
+            panic_with_error!(env, LeverageError::TargetUnreachable);
+        }
+
+        max_borrow
+    }
+
+    /// Borrows `total_borrow` of debt in rounds, swapping each round into
+    /// collateral and supplying it with the next round's borrow
+    ///
+    /// `supply` is collateral the contract holds that goes in with the first
+    /// borrow. Rounds stop just above Blend's liquidation line, or at
+    /// `target_c_factor` if that is lower, which also has to clear it. Each
+    /// round swaps no more than the price impact limit allows.
+    fn lever_up(
+        env: &Env,
+        config: &Config,
+        supply: i128,
+        target_c_factor: i128,
+        total_borrow: i128,
+        limits: &SwapLimits,
+        event: &mut LeverageUpEvent,
+    ) -> Positions {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let round_c_factor = match blend::min_c_factor(env, &collateral_reserve, &debt_reserve) {
+            Some(min_c_factor) if target_c_factor > min_c_factor => {
+                target_c_factor.min(min_c_factor + ROUND_BUFFER)
             }
+            _ => panic_with_error!(env, LeverageError::TargetUnreachable),
         };
 
-        if withdraw_amount > 0 {
-            // Withdraw collateral
-            blend::withdraw(
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+
+        let current_contract = env.current_contract_address();
+        let mut positions = blend::get_positions(env, config, &current_contract);
+        let path = swap::lever_path(env, config);
+        let routes = vec![env, path.clone()];
+        Self::require_reachable_borrow(
+            env,
+            config,
+            &positions,
+            supply,
+            round_c_factor,
+            total_borrow,
+            &path,
+        );
+
+        // Collateral waiting to be supplied with the next borrow
+        let mut pending = supply;
+        let mut borrowed = 0;
+        let mut rounds = 0;
+        while borrowed < total_borrow {
+            let collateral = blend::collateral_balance(env, &positions, &collateral_reserve) + pending;
+            let debt = blend::debt_balance(env, &positions, &debt_reserve);
+
+            // Debt the current collateral carries at the round c-factor
+            let max_debt = collateral
+                .fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar)
+                .fixed_mul_floor(env, &BPS, &round_c_factor)
+                .fixed_mul_floor(env, &debt_reserve.scalar, &debt_price);
+            let borrow_amount = (total_borrow - borrowed).min(max_debt - debt);
+            if rounds == MAX_ROUNDS || borrow_amount <= 0 {
+                panic_with_error!(env, LeverageError::TargetUnreachable);
+            }
+
+            // Leave what would move the pairs past the price impact limit to
+            // later rounds
+            let borrow_amount = swap::max_amount_in(env, config, borrow_amount, &routes);
+            if borrow_amount <= 0 {
+                panic_with_error!(env, LeverageError::PriceImpactExceeded);
+            }
+
+            let mut requests = blend::RequestBuilder::new(env, config);
+            if pending > 0 {
+                requests = requests.supply(pending);
+            }
+            positions = requests
+                .borrow(borrow_amount)
+                .submit(&current_contract, &current_contract);
+
+            let amounts_out = swap::get_amounts_out(env, config, borrow_amount, path.clone());
+            let quoted_out = amounts_out.last().unwrap_or(0);
+            swap::require_oracle_price(
                 env,
                 config,
+                &path,
+                borrow_amount,
+                quoted_out,
+                limits.max_slippage_bps,
+            );
+            let amounts = swap::swap_exact_tokens_for_tokens(
+                env,
+                config,
+                borrow_amount,
+                quoted_out,
+                path.clone(),
                 &current_contract,
-                &current_contract,
+                limits.deadline,
+            );
+
+            // Venues that quote cautiously can pay out a little more
+            pending = amounts.last().unwrap_or(0);
+            borrowed += borrow_amount;
+            event.swap_out += pending;
+            event.supplied += pending;
+            rounds += 1;
+        }
+        if pending > 0 {
+            positions = blend::deposit(env, config, &current_contract, pending);
+        }
+        event.borrowed += borrowed;
+        event.swap_in += borrowed;
+
+        positions
+    }
+
+    /// Panics with `LeverageTooHigh` unless `MAX_ROUNDS` rounds of `lever_up`
+    /// can borrow `total_borrow` once `supply` more collateral goes in
+    ///
+    /// A round borrows the headroom the collateral leaves at
+    /// `round_c_factor`, and the swap hands `swap_return` of it back as
+    /// collateral, so each round's headroom is the last one's times
+    /// `swap_return / round_c_factor`. A target the rounds only reach as the
+    /// limit of that series, like one at `round_c_factor` itself, is refused.
+    fn require_reachable_borrow(
+        env: &Env,
+        config: &Config,
+        positions: &Positions,
+        supply: i128,
+        round_c_factor: i128,
+        total_borrow: i128,
+        path: &Vec<Address>,
+    ) {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+
+        let collateral = blend::collateral_balance(env, positions, &collateral_reserve) + supply;
+        let debt = blend::debt_balance(env, positions, &debt_reserve);
+        let collateral_value = collateral.fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar);
+        let debt_value = debt.fixed_mul_ceil(env, &debt_price, &debt_reserve.scalar);
+        let borrow_value = total_borrow.fixed_mul_ceil(env, &debt_price, &debt_reserve.scalar);
+        if borrow_value <= 0 {
+            return;
+        }
+
+        // Collateral value the router returns per unit of debt value sold, in bps
+        let amounts_out = swap::get_amounts_out(env, config, total_borrow, path.clone());
+        let swap_return = amounts_out
+            .last()
+            .unwrap_or(0)
+            .fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_floor(env, &BPS, &borrow_value);
+
+        let mut headroom = collateral_value.fixed_mul_floor(env, &BPS, &round_c_factor) - debt_value;
+        let mut reachable = 0;
+        for _ in 0..MAX_ROUNDS {
+            if headroom <= 0 {
+                break;
+            }
+            reachable += headroom;
+            headroom = headroom
+                .fixed_mul_floor(env, &swap_return, &BPS)
+                .fixed_mul_floor(env, &BPS, &round_c_factor);
+        }
+        if reachable < borrow_value {
+            panic_with_error!(env, LeverageError::LeverageTooHigh);
+        }
+    }
+
+    /// Calculates how much debt to borrow, and swap into collateral, for the
+    /// position in `info` to come down to `target_c_factor`
+    fn calculate_lever_up_amount(
+        env: &Env,
+        config: &Config,
+        info: &PositionInfo,
+    ) -> i128 {
+        let target_c_factor = config.target_c_factor;
+        let excess = BPS * info.collateral_value - target_c_factor * info.debt_value;
+        if excess <= 0 || target_c_factor <= BPS {
+            return 0;
+        }
+
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+
+        // Collateral value the router returns per unit of debt value sold, in
+        // bps, quoted on the borrow a perfect swap would need
+        let estimate_value = excess / (target_c_factor - BPS);
+        let estimate = estimate_value.fixed_mul_floor(env, &debt_reserve.scalar, &debt_price);
+        if estimate <= 0 {
+            return 0;
+        }
+        let path = swap::lever_path(env, config);
+        let amounts_out = swap::get_amounts_out(env, config, estimate, path);
+        let swap_return = amounts_out
+            .last()
+            .unwrap_or(0)
+            .fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_floor(env, &BPS, &estimate_value);
+
+        // Borrowing B adds B * swap_return of collateral, so solve
+        // (collateral + B * swap_return) = target * (debt + B) for B
+        let borrow_value = excess / (target_c_factor - swap_return);
+        borrow_value.fixed_mul_floor(env, &debt_reserve.scalar, &debt_price)
+    }
+
+    /// Repays debt fronted by `funder` down to `target_c_factor`, or all of
+    /// it when there is none, then withdraws and swaps just enough collateral
+    /// to pay the funder back
+    fn delever_fronted(
+        env: &Env,
+        config: &Config,
+        funder: &Address,
+        target_c_factor: Option<i128>,
+        limits: &SwapLimits,
+        event: &mut DeleverageEvent,
+    ) -> Positions {
+        let current_contract = env.current_contract_address();
+        let positions = blend::get_positions(env, config, &current_contract);
+        let repay_amount = match target_c_factor {
+            Some(c_factor) => Self::calculate_repay_amount(env, config, &positions, 0, c_factor),
+            None => {
+                let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+                blend::debt_balance(env, &positions, &debt_reserve)
+            }
+        };
+
+        let debt_client = token::Client::new(env, &config.debt_asset);
+        debt_client.transfer(funder, &current_contract, &repay_amount);
+        event.flash_amount += repay_amount;
+        event.repaid += repay_amount;
+
+        // Collateral that buys the fronted debt back
+        let routes = swap::delever_routes(env, config);
+        let collateral_to_swap = swap::get_amount_in_split(env, config, repay_amount, &routes);
+
+        swap::require_oracle_price(
+            env,
+            config,
+            &routes.get_unchecked(0),
+            collateral_to_swap,
+            repay_amount,
+            limits.max_slippage_bps,
+        );
+
+        // One submit, so the health check only sees the position after both
+        let positions = blend::RequestBuilder::new(env, config)
+            .repay(repay_amount)
+            .withdraw(collateral_to_swap)
+            .submit(&current_contract, &current_contract);
+        let (collateral_in, debt_out) = swap::swap_tokens_for_exact_tokens_split(
+            env,
+            config,
+            repay_amount,
+            collateral_to_swap,
+            &routes,
+            &current_contract,
+            limits.deadline,
+        );
+        debt_client.transfer(&current_contract, funder, &repay_amount);
+        event.withdrawn += collateral_to_swap;
+        event.swap_in += collateral_in;
+        event.swap_out += debt_out;
+
+        Self::repay_overshoot(env, config, positions, debt_out - repay_amount, event)
+    }
+
+    /// Repays `overshoot`, debt a swap bought past what it was asked for,
+    /// which venues emulating exact output swaps can do
+    ///
+    /// Left for the caller to sweep once there is no debt to repay.
+    fn repay_overshoot(
+        env: &Env,
+        config: &Config,
+        positions: Positions,
+        overshoot: i128,
+        event: &mut DeleverageEvent,
+    ) -> Positions {
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let owed = blend::debt_balance(env, &positions, &debt_reserve);
+        if overshoot <= 0 || owed == 0 {
+            return positions;
+        }
+        let current_contract = env.current_contract_address();
+        let positions = blend::repay(env, config, &current_contract, overshoot.min(owed));
+        event.repaid += owed - blend::debt_balance(env, &positions, &debt_reserve);
+        positions
+    }
+
+    /// Repays debt with the position's own collateral until it sits at
+    /// `target_c_factor`, or until the debt is gone when there is none
+    ///
+    /// Runs in rounds like `open_position`: each withdraws at most what keeps
+    /// the position just above Blend's liquidation line and what the price
+    /// impact limit lets it swap, swaps it and repays. When the position is
+    /// too close to the line for `MAX_ROUNDS` rounds to get there, the owner
+    /// fronts the repayment instead and is paid back from the collateral.
+    fn unwind(
+        env: &Env,
+        config: &Config,
+        target_c_factor: Option<i128>,
+        limits: &SwapLimits,
+        event: &mut DeleverageEvent,
+    ) -> Positions {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let round_c_factor = match blend::min_c_factor(env, &collateral_reserve, &debt_reserve) {
+            Some(min_c_factor) => min_c_factor + ROUND_BUFFER,
+            None => panic_with_error!(env, LeverageError::TargetUnreachable),
+        };
+
+        let current_contract = env.current_contract_address();
+        let mut positions = blend::get_positions(env, config, &current_contract);
+        let routes = swap::delever_routes(env, config);
+        let repay_amount = match target_c_factor {
+            Some(c_factor) => Self::calculate_repay_amount(env, config, &positions, 0, c_factor),
+            None => blend::debt_balance(env, &positions, &debt_reserve),
+        };
+        if repay_amount > 0
+            && !Self::rounds_can_repay(env, config, &positions, round_c_factor, repay_amount, &routes)
+        {
+            // Too close to liquidation to withdraw much, so the owner fronts
+            // the repayment the way a keeper does in `rebalance`
+            return Self::delever_fronted(
+                env,
+                config,
+                &config.owner,
+                target_c_factor,
+                limits,
+                event,
+            );
+        }
+
+        // Debt swapped for but not yet repaid, folded into the next round's
+        // withdrawal
+        let mut pending = 0;
+        let mut rounds = 0;
+        loop {
+            let repay_amount = match target_c_factor {
+                Some(c_factor) => {
+                    Self::calculate_repay_amount(env, config, &positions, pending, c_factor)
+                }
+                // Repaying `pending` burns dTokens rounded down, which can
+                // leave a unit more owed than the difference
+                None => match blend::debt_balance(env, &positions, &debt_reserve) - pending {
+                    rest if rest > 0 && pending > 0 => rest + 1,
+                    rest => rest,
+                },
+            };
+            if repay_amount <= 0 {
+                if pending > 0 {
+                    positions = blend::repay(env, config, &current_contract, pending);
+                }
+                break;
+            }
+            if rounds == MAX_ROUNDS {
+                panic_with_error!(env, LeverageError::TargetUnreachable);
+            }
+
+            // Collateral that buys back the rest of the repayment
+            let collateral_for_repay =
+                swap::get_amount_in_split(env, config, repay_amount, &routes);
+
+            let withdraw_amount = Self::calculate_withdraw_amount(
+                env,
+                config,
+                &positions,
+                pending,
+                round_c_factor,
+            ).min(collateral_for_repay);
+            if withdraw_amount <= 0 {
+                panic_with_error!(env, LeverageError::TargetUnreachable);
+            }
+
+            // Leave what would move the pairs past the price impact limit to
+            // later rounds
+            let withdraw_amount = swap::max_amount_in(env, config, withdraw_amount, &routes);
+            if withdraw_amount <= 0 {
+                panic_with_error!(env, LeverageError::PriceImpactExceeded);
+            }
+
+            let min_out = swap::oracle_min_amount_out(
+                env,
+                config,
+                &routes.get_unchecked(0),
+                withdraw_amount,
+                limits.max_slippage_bps,
+            );
+
+            let mut requests = blend::RequestBuilder::new(env, config);
+            if pending > 0 {
+                requests = requests.repay(pending);
+            }
+            positions = requests
+                .withdraw(withdraw_amount)
+                .submit(&current_contract, &current_contract);
+            event.withdrawn += withdraw_amount;
+            event.repaid += pending;
+
+            if withdraw_amount == collateral_for_repay {
+                // Last round: buy exactly the rest of the repayment
+                if repay_amount < min_out {
+                    panic_with_error!(env, LeverageError::SlippageExceeded);
+                }
+                let (collateral_in, debt_out) = swap::swap_tokens_for_exact_tokens_split(
+                    env,
+                    config,
+                    repay_amount,
+                    withdraw_amount,
+                    &routes,
+                    &current_contract,
+                    limits.deadline,
+                );
+                // Repay all of it, including what venues emulating exact
+                // output buy past the ask, and book what Blend took rather
+                // than what it refunds on a close
+                let owed = blend::debt_balance(env, &positions, &debt_reserve);
+                positions = blend::repay(env, config, &current_contract, debt_out);
+                event.swap_in += collateral_in;
+                event.swap_out += debt_out;
+                event.repaid += owed - blend::debt_balance(env, &positions, &debt_reserve);
+                break;
+            }
+
+            pending = swap::swap_exact_tokens_split(
+                env,
+                config,
                 withdraw_amount,
+                min_out,
+                &routes,
+                &current_contract,
+                limits.deadline,
             );
+            event.swap_in += withdraw_amount;
+            event.swap_out += pending;
+            rounds += 1;
+        }
+
+        positions
+    }
+
+    /// Whether `MAX_ROUNDS` rounds of `unwind`, each withdrawing down to
+    /// `round_c_factor`, can sell the collateral that buys `repay_amount`
+    ///
+    /// Repaying the debt a round's withdrawal buys lets the next round
+    /// withdraw `round_c_factor` times its debt value, so the withdrawals
+    /// grow by `round_c_factor` times the debt value bought per collateral
+    /// value sold.
+    fn rounds_can_repay(
+        env: &Env,
+        config: &Config,
+        positions: &Positions,
+        round_c_factor: i128,
+        repay_amount: i128,
+        routes: &Vec<Vec<Address>>,
+    ) -> bool {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+
+        let collateral_for_repay = swap::get_amount_in_split(env, config, repay_amount, routes);
+        let collateral_value = collateral_for_repay.fixed_mul_ceil(env, &collateral_price, &collateral_reserve.scalar);
+        let repay_value = repay_amount.fixed_mul_floor(env, &debt_price, &debt_reserve.scalar);
+        if collateral_value <= 0 {
+            return true;
+        }
+        let growth = repay_value
+            .fixed_mul_floor(env, &round_c_factor, &collateral_value);
+
+        let mut withdrawable = Self::calculate_withdraw_amount(env, config, positions, 0, round_c_factor);
+        let mut reachable = 0;
+        for _ in 0..MAX_ROUNDS {
+            if withdrawable <= 0 || reachable >= collateral_for_repay {
+                break;
+            }
+            reachable += withdrawable;
+            withdrawable = withdrawable.fixed_mul_floor(env, &growth, &BPS);
+        }
+        reachable >= collateral_for_repay
+    }
+
+    /// Calculates how much debt to repay, by selling collateral through the
+    /// router, for the position to end up at `c_factor` (collateral value /
+    /// debt value, in bps), on top of `repay` already waiting to be paid back
+    ///
+    /// Returns zero if the position is already at or above `c_factor`.
+    fn calculate_repay_amount(
+        env: &Env,
+        config: &Config,
+        positions: &Positions,
+        repay: i128,
+        c_factor: i128,
+    ) -> i128 {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+
+        let collateral = blend::collateral_balance(env, positions, &collateral_reserve);
+        let debt = (blend::debt_balance(env, positions, &debt_reserve) - repay).max(0);
+        if debt == 0 {
+            return 0;
         }
 
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+        let collateral_value = collateral.fixed_mul_floor(env, &collateral_price, &collateral_reserve.scalar);
+        let debt_value = debt.fixed_mul_ceil(env, &debt_price, &debt_reserve.scalar);
+
+        // Collateral value the router takes per unit of debt value bought, in bps
+        let routes = swap::delever_routes(env, config);
+        let swap_cost = swap::get_amount_in_split(env, config, debt, &routes)
+            .fixed_mul_ceil(env, &collateral_price, &collateral_reserve.scalar)
+            .fixed_mul_ceil(env, &BPS, &debt_value);
+        if c_factor <= swap_cost {
+            panic_with_error!(env, LeverageError::TargetUnreachable);
+        }
+
+        // Repaying R costs R * swap_cost of collateral, so solve
+        // (collateral - R * swap_cost) = c_factor * (debt - R) for R
+        let shortfall = c_factor * debt_value - BPS * collateral_value;
+        if shortfall <= 0 {
+            return 0;
+        }
+        let repay_value = shortfall / (c_factor - swap_cost);
+        repay_value
+            .fixed_mul_ceil(env, &debt_reserve.scalar, &debt_price)
+            .min(debt)
+    }
+
+    fn handle_deleverage(
+        env: &Env,
+        config: &Config,
+        flash_amount: i128,
+        fee: i128,
+        limits: &SwapLimits,
+        event: &mut DeleverageEvent,
+    ) -> Positions {
+        let current_contract = env.current_contract_address();
+        let collateral_client = token::Client::new(env, &config.collateral_asset);
+
         // Now we have collateral tokens, need to swap some to debt tokens to repay flash loan
         let required_debt = flash_amount + fee;
 
         // Calculate how much collateral we need to swap
-        let path = vec![env, config.collateral_asset.clone(), config.debt_asset.clone()];
-        let amounts_in = swap::get_amounts_in(env, config, required_debt, path.clone());
-        let collateral_needed = amounts_in.get(0).unwrap_or(0);
+        let routes = swap::delever_routes(env, config);
+        let collateral_needed = swap::get_amount_in_split(env, config, required_debt, &routes);
 
         // Add slippage buffer
-        let collateral_to_swap = swap::calculate_max_amount_in(collateral_needed, 50); // 0.5% slippage
+        let collateral_to_swap = swap::calculate_max_amount_in(collateral_needed, limits.max_slippage_bps);
 
-        // Swap collateral for debt tokens to repay flash loan
-        swap::swap_exact_tokens_for_tokens(
+        // Collateral above the target once the flash loan is repaid
+        let positions = blend::get_positions(env, config, &current_contract);
+        let withdraw_amount = Self::calculate_withdraw_amount(
             env,
             config,
+            &positions,
+            flash_amount,
+            config.target_c_factor,
+        );
+        if withdraw_amount < collateral_to_swap {
+            // Holding the target leaves too little collateral to repay the flash loan
+            panic_with_error!(env, LeverageError::TargetUnreachable);
+        }
+
+        // Repay debt with the flash loaned tokens and withdraw collateral,
+        // which Blend caps at the position, in one submit
+        let collateral_before = collateral_client.balance(&current_contract);
+        let positions = RequestBuilder::new(env, config)
+            .repay(flash_amount)
+            .withdraw(withdraw_amount)
+            .submit(&current_contract, &current_contract);
+        event.repaid += flash_amount;
+        event.withdrawn += collateral_client.balance(&current_contract) - collateral_before;
+
+        // Sell only the collateral it takes to buy the flash loan repayment
+        let (collateral_in, debt_out) = swap::swap_tokens_for_exact_tokens_split(
+            env,
+            config,
+            required_debt,
             collateral_to_swap,
-            required_debt, // Minimum we need
-            path,
+            &routes,
             &current_contract,
+            limits.deadline,
         );
+        swap::require_oracle_price(
+            env,
+            config,
+            &routes.get_unchecked(0),
+            collateral_in,
+            debt_out,
+            limits.max_slippage_bps,
+        );
+        event.swap_in += collateral_in;
+        event.swap_out += debt_out;
+        let positions = Self::repay_overshoot(env, config, positions, debt_out - required_debt, event);
 
         // Transfer remaining collateral to owner (not caller)
         let final_collateral_balance = collateral_client.balance(&current_contract);
         if final_collateral_balance > 0 {
             collateral_client.transfer(&current_contract, &config.owner, &final_collateral_balance);
         }
+
+        positions
+    }
+
+    /// Calculates how much collateral to withdraw so the remaining position
+    /// sits at `c_factor` (collateral value / debt value, in bps), pricing
+    /// both assets with the oracle, once `repay` more debt is paid back.
+    ///
+    /// Withdraws everything once the debt is fully repaid.
+    fn calculate_withdraw_amount(
+        env: &Env,
+        config: &Config,
+        positions: &Positions,
+        repay: i128,
+        c_factor: i128,
+    ) -> i128 {
+        let collateral_reserve = blend::get_reserve(env, config, &config.collateral_asset);
+        let debt_reserve = blend::get_reserve(env, config, &config.debt_asset);
+
+        let collateral = blend::collateral_balance(env, positions, &collateral_reserve);
+        let remaining_debt = (blend::debt_balance(env, positions, &debt_reserve) - repay).max(0);
+        if remaining_debt == 0 {
+            // Blend caps withdrawals at the position, so asking for one more
+            // than the balance also clears any bToken dust
+            return collateral + 1;
+        }
+
+        let collateral_price = oracle::get_price(env, config, &config.collateral_asset);
+        let debt_price = oracle::get_price(env, config, &config.debt_asset);
+
+        // Collateral that keeps the remaining debt at the target
+        let debt_value = remaining_debt.fixed_mul_ceil(env, &debt_price, &debt_reserve.scalar);
+        let required_collateral = debt_value
+            .fixed_mul_ceil(env, &c_factor, &BPS)
+            .fixed_mul_ceil(env, &collateral_reserve.scalar, &collateral_price);
+
+        if collateral > required_collateral {
+            collateral - required_collateral
+        } else {
+            0
+        }
+    }
+}
+
+#[contractimpl]
+impl CustomAccountInterface for LeverageContract {
+    type Signature = ();
+    type Error = LeverageError;
+
+    /// Authorizes, with the owner's approval, a Blend `flash_loan` taken out
+    /// on this contract's position and the pool pulling the collateral it
+    /// supplies
+    ///
+    /// The loan has to borrow the debt asset into this contract, and its
+    /// requests may only supply collateral. Authorizing it starts the flash
+    /// loan `exec_op` then waits for.
+    #[allow(non_snake_case)]
+    fn __check_auth(
+        env: Env,
+        _signature_payload: Hash<32>,
+        _signature: (),
+        auth_contexts: Vec<Context>,
+    ) -> Result<(), LeverageError> {
+        let config = get_config(&env);
+        config.owner.require_auth();
+
+        let current_contract = env.current_contract_address();
+        for context in auth_contexts.iter() {
+            let context = match context {
+                Context::Contract(context) => context,
+                _ => return Err(LeverageError::Unauthorized),
+            };
+
+            if context.contract == config.blend_pool
+                && context.fn_name == Symbol::new(&env, "flash_loan")
+            {
+                let flash_loan: blend::FlashLoan = context
+                    .args
+                    .get(1)
+                    .and_then(|arg| arg.try_into_val(&env).ok())
+                    .ok_or(LeverageError::BadRequest)?;
+                let requests: Vec<Request> = context
+                    .args
+                    .get(2)
+                    .and_then(|arg| arg.try_into_val(&env).ok())
+                    .ok_or(LeverageError::BadRequest)?;
+                if flash_loan.contract != current_contract {
+                    return Err(LeverageError::Unauthorized);
+                }
+                if flash_loan.asset != config.debt_asset {
+                    return Err(LeverageError::UnsupportedToken);
+                }
+
+                let mut supply = 0;
+                for request in requests.iter() {
+                    if request.request_type != RequestType::SupplyCollateral as u32
+                        || request.address != config.collateral_asset
+                    {
+                        return Err(LeverageError::BadRequest);
+                    }
+                    supply += request.amount;
+                }
+                if supply <= 0 {
+                    return Err(LeverageError::ZeroBalance);
+                }
+
+                set_flash_loan(&env, &FlashLoan {
+                    lender: config.blend_pool.clone(),
+                    token: flash_loan.asset,
+                    amount: flash_loan.amount,
+                    supply,
+                });
+            } else if context.contract != config.collateral_asset
+                || context.fn_name != Symbol::new(&env, "transfer")
+                || context.args.get(1).and_then(|arg| arg.try_into_val(&env).ok())
+                    != Some(config.blend_pool.clone())
+            {
+                return Err(LeverageError::Unauthorized);
+            }
+        }
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -6,5 +6,50 @@ use soroban_sdk::contracterror;
 pub enum LeverageError {
     BadRequest = 123,
     Unauthorized = 124,
-
+    /// The requested c-factor cannot be reached from the current position
+    TargetUnreachable = 125,
+    /// The contract is paused and the call would add risk
+    Paused = 126,
+    /// A config value is out of range, the c-factor band is out of order or a
+    /// call asks for more slippage than the configured maximum
+    InvalidConfig = 127,
+    /// A flash loan arrived in neither the collateral nor the debt asset
+    UnsupportedToken = 128,
+    /// The router cannot deliver the output a repayment or withdrawal needs
+    InsufficientSwapOutput = 129,
+    /// The router's price is further from the oracle than the call allows
+    SlippageExceeded = 130,
+    /// The swap deadline has already passed
+    DeadlineExpired = 131,
+    /// There is nothing to act on
+    ZeroBalance = 132,
+    /// The position would end up below the `min_c_factor` floor or Blend's
+    /// liquidation line
+    UnhealthyPosition = 133,
+    /// The Blend pool or one of its reserves is frozen
+    PoolFrozen = 134,
+    /// The pool or the swap pair lacks the liquidity the call needs
+    InsufficientLiquidity = 135,
+    /// Supplying would take the collateral reserve past its cap
+    SupplyCapExceeded = 136,
+    /// The Blend pool rejected the request for another reason
+    PoolRejected = 137,
+    /// The router has no pair for a hop of the swap path
+    PairNotFound = 138,
+    /// The router rejected the swap for another reason
+    SwapFailed = 139,
+    /// The swap would move a pair's price further than the configured limit;
+    /// a smaller amount, over more flash loan rounds, may still go through
+    PriceImpactExceeded = 140,
+    /// The oracle has no positive price for one of the assets
+    OraclePriceUnavailable = 141,
+    /// The position is already at or past the requested c-factor, or inside
+    /// the rebalance band
+    AlreadyAtTarget = 142,
+    /// The oracle's last price for one of the assets is older than the
+    /// configured maximum age
+    StalePrice = 143,
+    /// The target leverage is beyond what `MAX_ROUNDS` rounds of borrowing
+    /// against the collateral can reach
+    LeverageTooHigh = 144,
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use soroban_sdk::{contracttype, symbol_short, Address, BytesN, Env, IntoVal, Symbol, Val};
+use crate::{
+    blend::{self, Positions},
+    storage::Config,
+};
+
+/// Emitted whenever debt is added to the position, through a flash loan or in
+/// rounds. Amounts are in underlying tokens.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct LeverageUpEvent {
+    /// Collateral flash loaned in, zero outside `exec_op`
+    pub flash_amount: i128,
+    pub fee: i128,
+    /// Collateral supplied to Blend
+    pub supplied: i128,
+    /// Debt borrowed from Blend
+    pub borrowed: i128,
+    /// Debt sold to the router
+    pub swap_in: i128,
+    /// Collateral bought from the router
+    pub swap_out: i128,
+    /// Collateral in the position afterwards, zero for Blend flash loans
+    pub collateral: i128,
+    /// Debt in the position afterwards, zero for Blend flash loans
+    pub debt: i128,
+}
+
+/// Emitted whenever debt is paid down, through a flash loan, with keeper
+/// capital or with the position's own collateral. Amounts are in underlying
+/// tokens.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct DeleverageEvent {
+    /// Debt flash loaned in, or fronted by a keeper
+    pub flash_amount: i128,
+    pub fee: i128,
+    /// Debt repaid to Blend
+    pub repaid: i128,
+    /// Collateral withdrawn from Blend
+    pub withdrawn: i128,
+    /// Collateral sold to the router
+    pub swap_in: i128,
+    /// Debt bought from the router
+    pub swap_out: i128,
+    /// Collateral in the position afterwards
+    pub collateral: i128,
+    /// Debt in the position afterwards
+    pub debt: i128,
+}
+
+/// Emitted when Blend rewards are claimed and paid out
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct ClaimEvent {
+    pub to: Address,
+    pub amount: i128,
+}
+
+/// Emitted when claimed Blend rewards are sold for collateral and supplied
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct CompoundEvent {
+    /// Rewards claimed from Blend
+    pub claimed: i128,
+    /// Collateral bought with them and supplied
+    pub supplied: i128,
+}
+
+/// Publishes a leverage-up, filling in the position it left behind
+pub fn leverage_up(e: &Env, config: &Config, mut event: LeverageUpEvent, positions: &Positions) {
+    (event.collateral, event.debt) = balances(e, config, positions);
+    e.events().publish((symbol_short!("lever_up"),), event);
+}
+
+/// Publishes a leverage-up from inside a Blend flash loan. The pool applies
+/// it after `exec_op` returns and cannot be called before then, so the
+/// position it leaves behind is not filled in.
+pub fn blend_leverage_up(e: &Env, event: LeverageUpEvent) {
+    e.events().publish((symbol_short!("lever_up"),), event);
+}
+
+/// Publishes a deleverage, filling in the position it left behind
+pub fn deleverage(e: &Env, config: &Config, mut event: DeleverageEvent, positions: &Positions) {
+    (event.collateral, event.debt) = balances(e, config, positions);
+    e.events().publish((symbol_short!("delever"),), event);
+}
+
+pub fn claim(e: &Env, to: &Address, amount: i128) {
+    e.events().publish(
+        (symbol_short!("claim"),),
+        ClaimEvent {
+            to: to.clone(),
+            amount,
+        },
+    );
+}
+
+pub fn compound(e: &Env, claimed: i128, supplied: i128) {
+    e.events()
+        .publish((symbol_short!("compound"),), CompoundEvent { claimed, supplied });
+}
+
+/// Publishes the new value of the config field `field`
+pub fn config_updated<T: IntoVal<Env, Val>>(e: &Env, field: Symbol, value: T) {
+    e.events().publish((symbol_short!("config"), field), value);
+}
+
+pub fn owner_proposed(e: &Env, new_owner: &Address) {
+    e.events()
+        .publish((symbol_short!("owner"), symbol_short!("proposed")), new_owner.clone());
+}
+
+pub fn owner_accepted(e: &Env, old_owner: &Address, new_owner: &Address) {
+    e.events().publish(
+        (symbol_short!("owner"), symbol_short!("accepted")),
+        (old_owner.clone(), new_owner.clone()),
+    );
+}
+
+pub fn paused(e: &Env, caller: &Address) {
+    e.events().publish((symbol_short!("paused"),), caller.clone());
+}
+
+pub fn unpaused(e: &Env, owner: &Address) {
+    e.events().publish((symbol_short!("unpaused"),), owner.clone());
+}
+
+pub fn upgraded(e: &Env, new_wasm_hash: &BytesN<32>) {
+    e.events().publish((symbol_short!("upgrade"),), new_wasm_hash.clone());
+}
+
+/// Collateral and debt of `positions`, in underlying tokens
+fn balances(e: &Env, config: &Config, positions: &Positions) -> (i128, i128) {
+    let collateral_reserve = blend::get_reserve(e, config, &config.collateral_asset);
+    let debt_reserve = blend::get_reserve(e, config, &config.debt_asset);
+    (
+        blend::collateral_balance(e, positions, &collateral_reserve),
+        blend::debt_balance(e, positions, &debt_reserve),
+    )
+}
@@ -3,9 +3,15 @@
 pub mod contract;
 mod blend;
 mod errors;
+mod events;
+mod oracle;
+mod position;
 mod storage;
 mod swap;
 
 pub use contract::LeverageContract;
 pub use contract::LeverageContractClient;
-pub use errors::LeverageError;
\ No newline at end of file
+pub use errors::LeverageError;
+pub use events::{ClaimEvent, CompoundEvent, DeleverageEvent, LeverageUpEvent};
+pub use position::PositionInfo;
+pub use storage::{Config, Venue};
\ No newline at end of file
@@ -0,0 +1,44 @@
+use soroban_sdk::{contracttype, panic_with_error, Address, Env, IntoVal, Symbol};
+use crate::{errors::LeverageError, storage::{Config, get_max_price_age}};
+
+/// SEP-40 asset identifier
+#[derive(Clone)]
+#[contracttype]
+pub enum Asset {
+    Stellar(Address),
+    Other(Symbol),
+}
+
+/// SEP-40 price record
+#[derive(Clone)]
+#[contracttype]
+pub struct PriceData {
+    pub price: i128,
+    pub timestamp: u64,
+}
+
+/// Get the latest oracle price for an asset
+///
+/// Prices share the oracle's decimals, so they are only meaningful relative to
+/// each other. A price older than the configured maximum age is refused, as
+/// it would size borrows and guard swaps against a market that has moved.
+pub fn get_price(
+    e: &Env,
+    config: &Config,
+    asset: &Address,
+) -> i128 {
+    let price_data = e.invoke_contract::<Option<PriceData>>(
+        &config.oracle,
+        &Symbol::new(e, "lastprice"),
+        (Asset::Stellar(asset.clone()),).into_val(e),
+    );
+
+    let price_data = match price_data {
+        Some(price_data) if price_data.price > 0 => price_data,
+        _ => panic_with_error!(e, LeverageError::OraclePriceUnavailable),
+    };
+    if price_data.timestamp.saturating_add(get_max_price_age(e)) < e.ledger().timestamp() {
+        panic_with_error!(e, LeverageError::StalePrice);
+    }
+    price_data.price
+}
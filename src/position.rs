@@ -0,0 +1,88 @@
+use soroban_sdk::{contracttype, Env};
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::{
+    blend::{self, SCALAR_7},
+    contract::BPS,
+    oracle,
+    storage::Config,
+};
+
+/// Snapshot of the contract's Blend position
+///
+/// Amounts are in underlying units, values and prices share the oracle's
+/// decimals. Ratios without debt (or, for leverage, without equity) are
+/// reported as `i128::MAX`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct PositionInfo {
+    /// Collateral supplied, in collateral units
+    pub collateral: i128,
+    /// Outstanding debt, in debt units
+    pub debt: i128,
+    pub collateral_value: i128,
+    pub debt_value: i128,
+    /// Collateral value / debt value, in bps
+    pub c_factor: i128,
+    /// Blend's health factor with c_factor and l_factor applied, 7 decimals
+    pub health_factor: i128,
+    /// Collateral value / equity, in bps
+    pub leverage: i128,
+    /// Collateral price at which the health factor reaches 1
+    pub liquidation_price: i128,
+}
+
+/// Builds a `PositionInfo` for `config`'s pool and assets
+pub fn get_position(e: &Env, config: &Config) -> PositionInfo {
+    let collateral_reserve = blend::get_reserve(e, config, &config.collateral_asset);
+    let debt_reserve = blend::get_reserve(e, config, &config.debt_asset);
+    let positions = blend::get_positions(e, config, &e.current_contract_address());
+
+    let collateral = blend::collateral_balance(e, &positions, &collateral_reserve);
+    let debt = blend::debt_balance(e, &positions, &debt_reserve);
+
+    let collateral_price = oracle::get_price(e, config, &config.collateral_asset);
+    let debt_price = oracle::get_price(e, config, &config.debt_asset);
+    let collateral_value = collateral.fixed_mul_floor(e, &collateral_price, &collateral_reserve.scalar);
+    let debt_value = debt.fixed_mul_ceil(e, &debt_price, &debt_reserve.scalar);
+
+    // Blend weighs collateral down by c_factor and liabilities up by l_factor
+    let c_factor = collateral_reserve.config.c_factor as i128;
+    let l_factor = debt_reserve.config.l_factor as i128;
+    let effective_collateral = collateral_value.fixed_mul_floor(e, &c_factor, &SCALAR_7);
+
+    let (position_c_factor, health_factor, liquidation_price) = if debt_value > 0 && l_factor > 0 {
+        let effective_liabilities = debt_value.fixed_mul_ceil(e, &SCALAR_7, &l_factor);
+        let liquidation_price = if collateral > 0 && c_factor > 0 {
+            effective_liabilities
+                .fixed_mul_ceil(e, &SCALAR_7, &c_factor)
+                .fixed_mul_ceil(e, &collateral_reserve.scalar, &collateral)
+        } else {
+            i128::MAX
+        };
+        (
+            collateral_value.fixed_mul_floor(e, &BPS, &debt_value),
+            effective_collateral.fixed_mul_floor(e, &SCALAR_7, &effective_liabilities),
+            liquidation_price,
+        )
+    } else {
+        (i128::MAX, i128::MAX, 0)
+    };
+
+    let equity = collateral_value - debt_value;
+    let leverage = if equity > 0 {
+        collateral_value.fixed_mul_floor(e, &BPS, &equity)
+    } else {
+        i128::MAX
+    };
+
+    PositionInfo {
+        collateral,
+        debt,
+        collateral_value,
+        debt_value,
+        c_factor: position_c_factor,
+        health_factor,
+        leverage,
+        liquidation_price,
+    }
+}
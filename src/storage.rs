@@ -1,10 +1,66 @@
-use soroban_sdk::{Address, Env, contracttype};
+use soroban_sdk::{Address, Env, Vec, contracttype};
 use soroban_sdk::unwrap::UnwrapOptimized;
-use crate::errors::LeverageError;
+use crate::blend::PoolClient;
+
+/// Layout of everything this contract keeps in storage. Bump it whenever a
+/// stored type changes and teach `migrate` to rewrite the older layout.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Rebalance band around the target, in bps, for configs migrated from a
+/// layout without one
+const MIGRATED_BAND_BPS: i128 = 1_000;
+/// Slippage limits, in bps, for configs migrated from a layout without them
+const MIGRATED_DEFAULT_SLIPPAGE_BPS: i128 = 100;
+const MIGRATED_MAX_SLIPPAGE_BPS: i128 = 500;
+/// Oldest oracle price (seconds) accepted until the owner sets a limit
+const DEFAULT_MAX_PRICE_AGE: u64 = 900;
 
 #[derive(Clone)]
 #[contracttype]
 pub struct Config {
+    pub owner: Address,
+    pub blend_pool: Address,
+    pub collateral_asset: Address,
+    pub debt_asset: Address,
+    pub reward_token: Address,
+    pub swap_router: Address,
+    pub oracle: Address,
+    /// Collateral value / debt value (bps) below which `rebalance` delevers
+    pub min_c_factor: i128,
+    /// Collateral value / debt value (bps) the position is steered to
+    pub target_c_factor: i128,
+    /// Collateral value / debt value (bps) above which `rebalance` levers up
+    pub max_c_factor: i128,
+    /// Slippage against the oracle (bps) used when a call does not pass one
+    pub default_slippage_bps: i128,
+    /// Largest slippage against the oracle (bps) a call may ask for
+    pub max_slippage_bps: i128,
+}
+
+/// DEX the contract swaps through
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum Venue {
+    /// Soroswap's router at `Config::swap_router`, over its constant product
+    /// pairs
+    SoroswapRouter,
+    /// Phoenix constant product pools, looked up by token pair on the
+    /// factory at this address
+    Phoenix(Address),
+    /// The Comet weighted pool at this address, the pool type Blend's
+    /// backstop uses, holding every token of the paths
+    Comet(Address),
+    /// Soroswap's aggregator at this address, trading Soroswap's pairs as
+    /// quoted by the router at `Config::swap_router`
+    SoroswapAggregator(Address),
+    /// Aqua pools, looked up by token pair on the router at this address
+    Aqua(Address),
+}
+
+/// `Config` as stored by schema version 1
+#[derive(Clone)]
+#[contracttype]
+pub struct ConfigV1 {
     pub owner: Address,
     pub blend_pool: Address,
     pub collateral_asset: Address,
@@ -14,10 +70,35 @@ pub struct Config {
     pub target_c_factor: i128,
 }
 
+/// A flash loan the owner has started and `exec_op` is waiting for
+#[derive(Clone, Eq, PartialEq)]
+#[contracttype]
+pub struct FlashLoan {
+    pub lender: Address,
+    pub token: Address,
+    pub amount: i128,
+    /// Collateral the Blend pool supplies from this contract once `exec_op`
+    /// returns, zero for other lenders
+    pub supply: i128,
+}
+
 #[derive(Clone)]
 #[contracttype]
 pub enum DataKey {
     Config,
+    PendingOwner,
+    SchemaVersion,
+    Guardian,
+    Paused,
+    FlashLender(Address),
+    FlashLoan,
+    ClaimIds,
+    LeverPath,
+    DeleverRoutes,
+    MaxPriceImpact,
+    CompoundPath,
+    MaxPriceAge,
+    Venue,
 }
 
 pub fn set_config(e: &Env, config: &Config) {
@@ -25,8 +106,185 @@ pub fn set_config(e: &Env, config: &Config) {
 }
 
 pub fn get_config(e: &Env) -> Config {
+    if get_schema_version(e) < SCHEMA_VERSION {
+        migrate(e);
+    }
     e.storage()
         .instance()
         .get(&DataKey::Config)
         .unwrap_optimized()
-}
\ No newline at end of file
+}
+
+pub fn set_flash_lender(e: &Env, lender: &Address, allowed: bool) {
+    let key = DataKey::FlashLender(lender.clone());
+    if allowed {
+        e.storage().instance().set(&key, &true);
+    } else {
+        e.storage().instance().remove(&key);
+    }
+}
+
+pub fn is_flash_lender(e: &Env, lender: &Address) -> bool {
+    e.storage()
+        .instance()
+        .has(&DataKey::FlashLender(lender.clone()))
+}
+
+/// Flash loans only live in temporary storage, so one that is never taken
+/// expires with its entry
+pub fn set_flash_loan(e: &Env, flash_loan: &FlashLoan) {
+    e.storage().temporary().set(&DataKey::FlashLoan, flash_loan);
+}
+
+/// Removes and returns the flash loan in progress, if any
+pub fn take_flash_loan(e: &Env) -> Option<FlashLoan> {
+    let flash_loan = e.storage().temporary().get(&DataKey::FlashLoan);
+    if flash_loan.is_some() {
+        e.storage().temporary().remove(&DataKey::FlashLoan);
+    }
+    flash_loan
+}
+
+pub fn set_claim_ids(e: &Env, ids: &Vec<u32>) {
+    e.storage().instance().set(&DataKey::ClaimIds, ids);
+}
+
+pub fn get_claim_ids(e: &Env) -> Option<Vec<u32>> {
+    e.storage().instance().get(&DataKey::ClaimIds)
+}
+
+pub fn set_lever_path(e: &Env, path: &Vec<Address>) {
+    e.storage().instance().set(&DataKey::LeverPath, path);
+}
+
+pub fn get_lever_path(e: &Env) -> Option<Vec<Address>> {
+    e.storage().instance().get(&DataKey::LeverPath)
+}
+
+pub fn set_delever_routes(e: &Env, routes: &Vec<Vec<Address>>) {
+    e.storage().instance().set(&DataKey::DeleverRoutes, routes);
+}
+
+pub fn get_delever_routes(e: &Env) -> Option<Vec<Vec<Address>>> {
+    e.storage().instance().get(&DataKey::DeleverRoutes)
+}
+
+pub fn set_compound_path(e: &Env, path: &Vec<Address>) {
+    e.storage().instance().set(&DataKey::CompoundPath, path);
+}
+
+pub fn get_compound_path(e: &Env) -> Option<Vec<Address>> {
+    e.storage().instance().get(&DataKey::CompoundPath)
+}
+
+pub fn remove_compound_path(e: &Env) {
+    e.storage().instance().remove(&DataKey::CompoundPath);
+}
+
+pub fn set_max_price_impact(e: &Env, max_price_impact_bps: i128) {
+    e.storage().instance().set(&DataKey::MaxPriceImpact, &max_price_impact_bps);
+}
+
+/// Largest price impact (bps) a swap may have, unlimited until set
+pub fn get_max_price_impact(e: &Env) -> Option<i128> {
+    e.storage().instance().get(&DataKey::MaxPriceImpact)
+}
+
+pub fn set_max_price_age(e: &Env, max_price_age: u64) {
+    e.storage().instance().set(&DataKey::MaxPriceAge, &max_price_age);
+}
+
+/// Oldest oracle price (seconds) the contract acts on
+pub fn get_max_price_age(e: &Env) -> u64 {
+    e.storage()
+        .instance()
+        .get(&DataKey::MaxPriceAge)
+        .unwrap_or(DEFAULT_MAX_PRICE_AGE)
+}
+
+pub fn set_venue(e: &Env, venue: &Venue) {
+    e.storage().instance().set(&DataKey::Venue, venue);
+}
+
+/// Venue swaps go through, Soroswap's router until the owner picks another
+pub fn get_venue(e: &Env) -> Venue {
+    e.storage()
+        .instance()
+        .get(&DataKey::Venue)
+        .unwrap_or(Venue::SoroswapRouter)
+}
+
+pub fn set_guardian(e: &Env, guardian: &Address) {
+    e.storage().instance().set(&DataKey::Guardian, guardian);
+}
+
+pub fn get_guardian(e: &Env) -> Option<Address> {
+    e.storage().instance().get(&DataKey::Guardian)
+}
+
+pub fn set_paused(e: &Env, paused: bool) {
+    e.storage().instance().set(&DataKey::Paused, &paused);
+}
+
+pub fn is_paused(e: &Env) -> bool {
+    e.storage()
+        .instance()
+        .get(&DataKey::Paused)
+        .unwrap_or(false)
+}
+
+pub fn set_schema_version(e: &Env, version: u32) {
+    e.storage().instance().set(&DataKey::SchemaVersion, &version);
+}
+
+/// Schema version of the stored data. Contracts deployed before it was
+/// recorded are on version 1.
+pub fn get_schema_version(e: &Env) -> u32 {
+    e.storage()
+        .instance()
+        .get(&DataKey::SchemaVersion)
+        .unwrap_or(1)
+}
+
+/// Rewrites data stored under an older schema into the current layout
+///
+/// Runs on the first config read after an upgrade. Fields an older layout
+/// lacks are filled from the Blend pool (the oracle) or with conservative
+/// defaults the owner can change afterwards.
+fn migrate(e: &Env) {
+    if get_schema_version(e) == 1 {
+        let old: ConfigV1 = e
+            .storage()
+            .instance()
+            .get(&DataKey::Config)
+            .unwrap_optimized();
+        let oracle = PoolClient::new(e, &old.blend_pool).get_config().oracle;
+        set_config(e, &Config {
+            owner: old.owner,
+            blend_pool: old.blend_pool,
+            collateral_asset: old.collateral_asset,
+            debt_asset: old.debt_asset,
+            reward_token: old.reward_token,
+            swap_router: old.swap_router,
+            oracle,
+            min_c_factor: old.target_c_factor - MIGRATED_BAND_BPS,
+            target_c_factor: old.target_c_factor,
+            max_c_factor: old.target_c_factor + MIGRATED_BAND_BPS,
+            default_slippage_bps: MIGRATED_DEFAULT_SLIPPAGE_BPS,
+            max_slippage_bps: MIGRATED_MAX_SLIPPAGE_BPS,
+        });
+    }
+    set_schema_version(e, SCHEMA_VERSION);
+}
+
+pub fn set_pending_owner(e: &Env, owner: &Address) {
+    e.storage().instance().set(&DataKey::PendingOwner, owner);
+}
+
+pub fn get_pending_owner(e: &Env) -> Option<Address> {
+    e.storage().instance().get(&DataKey::PendingOwner)
+}
+
+pub fn remove_pending_owner(e: &Env) {
+    e.storage().instance().remove(&DataKey::PendingOwner);
+}
@@ -0,0 +1,169 @@
+use soroban_sdk::{
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    contracttype, panic_with_error, vec, Address, BytesN, Env, IntoVal, InvokeError, Symbol, Val,
+    Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use crate::errors::LeverageError;
+use super::soroswap;
+
+/// Soroswap aggregator `Protocol`, the DEX a distribution trades on
+///
+/// Only Soroswap's pairs are traded, since they are the ones the router at
+/// `Config::swap_router` can quote.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[contracttype]
+#[repr(u32)]
+pub enum Protocol {
+    Soroswap = 0,
+}
+
+/// Share of an aggregator swap sent along `path` on one protocol
+#[derive(Clone)]
+#[contracttype]
+pub struct DexDistribution {
+    pub protocol_id: Protocol,
+    pub path: Vec<Address>,
+    pub parts: u32,
+    pub bytes: Option<Vec<BytesN<32>>>,
+}
+
+/// Quotes come from the router, which prices the same pairs the
+/// aggregator's Soroswap leg trades
+#[allow(clippy::too_many_arguments)]
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    aggregator: &Address,
+    router: &Address,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let amounts_out = soroswap::get_amounts_out(e, router, amount_in, path.clone());
+    if amounts_out.last().unwrap_optimized() < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        soroswap::require_price_impact(e, router, &path, &amounts_out, max_price_impact_bps);
+    }
+
+    // The aggregator holds the whole swap to the minimum, not its legs
+    let leg_args = (amount_in, 0i128, path.clone(), to.clone(), deadline).into_val(e);
+    authorize_router_swap(e, router, "swap_exact_tokens_for_tokens", leg_args, &path, amount_in);
+
+    let swap_args = vec![
+        e,
+        path.first().unwrap_optimized().to_val(),
+        path.last().unwrap_optimized().to_val(),
+        amount_in.into_val(e),
+        amount_out_min.into_val(e),
+        distribution(e, &path).into_val(e),
+        to.to_val(),
+        deadline.into_val(e),
+    ];
+    invoke_aggregator(e, aggregator, "swap_exact_tokens_for_tokens", swap_args)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    aggregator: &Address,
+    router: &Address,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    // The router pulls exactly what it quotes for the output
+    let amounts_in = soroswap::get_amounts_in(e, router, amount_out, path.clone());
+    let amount_in = amounts_in.get(0).unwrap_optimized();
+    if amount_in > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        soroswap::require_price_impact(e, router, &path, &amounts_in, max_price_impact_bps);
+    }
+
+    let leg_args = (amount_out, amount_in_max, path.clone(), to.clone(), deadline).into_val(e);
+    authorize_router_swap(e, router, "swap_tokens_for_exact_tokens", leg_args, &path, amount_in);
+
+    let swap_args = vec![
+        e,
+        path.first().unwrap_optimized().to_val(),
+        path.last().unwrap_optimized().to_val(),
+        amount_out.into_val(e),
+        amount_in_max.into_val(e),
+        distribution(e, &path).into_val(e),
+        to.to_val(),
+        deadline.into_val(e),
+    ];
+    invoke_aggregator(e, aggregator, "swap_tokens_for_exact_tokens", swap_args)
+}
+
+/// The whole swap along `path` on Soroswap
+fn distribution(e: &Env, path: &Vec<Address>) -> Vec<DexDistribution> {
+    vec![
+        e,
+        DexDistribution {
+            protocol_id: Protocol::Soroswap,
+            path: path.clone(),
+            parts: 1,
+            bytes: None,
+        },
+    ]
+}
+
+/// Authorizes the router swap the aggregator runs for this contract, and
+/// the router's transfer of `amount` of the first token in `path` into the
+/// first pair within it
+fn authorize_router_swap(
+    e: &Env,
+    router: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+    path: &Vec<Address>,
+    amount: i128,
+) {
+    let token_in = path.get(0).unwrap_optimized();
+    let pair = soroswap::first_pair(e, router, path);
+    e.authorize_as_current_contract(vec![
+        e,
+        InvokerContractAuthEntry::Contract(SubContractInvocation {
+            context: ContractContext {
+                contract: router.clone(),
+                fn_name: Symbol::new(e, fn_name),
+                args,
+            },
+            sub_invocations: vec![
+                e,
+                InvokerContractAuthEntry::Contract(SubContractInvocation {
+                    context: ContractContext {
+                        contract: token_in,
+                        fn_name: Symbol::new(e, "transfer"),
+                        args: (e.current_contract_address(), pair, amount).into_val(e),
+                    },
+                    sub_invocations: vec![e],
+                }),
+            ],
+        }),
+    ]);
+}
+
+/// Calls `fn_name` on the aggregator, returning the amounts of its one
+/// distribution, or failing with `SwapFailed` if it rejects the call
+fn invoke_aggregator(e: &Env, aggregator: &Address, fn_name: &str, args: Vec<Val>) -> Vec<i128> {
+    let result = e.try_invoke_contract::<Vec<Vec<i128>>, InvokeError>(
+        aggregator,
+        &Symbol::new(e, fn_name),
+        args,
+    );
+    match result {
+        Ok(Ok(amounts)) => amounts.get(0).unwrap_optimized(),
+        _ => panic_with_error!(e, LeverageError::SwapFailed),
+    }
+}
@@ -0,0 +1,246 @@
+use soroban_sdk::{
+    panic_with_error, vec, Address, BytesN, Env, IntoVal, InvokeError, Map, Symbol, TryFromVal,
+    Val, Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::errors::LeverageError;
+use super::IMPACT_SCALAR;
+
+/// Aqua lists several pools per token pair, so each hop goes to the pool
+/// quoting the most for it, and pays out to this contract, which sells it
+/// on in the next
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    router: &Address,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let (amounts_out, pools, price_kept) = quote_out(e, router, amount_in, &path);
+    if amounts_out.last().unwrap_optimized() < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        super::require_price_kept(e, price_kept, max_price_impact_bps);
+    }
+
+    let current_contract = e.current_contract_address();
+    let mut amounts = vec![e, amount_in];
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let pool = pools.get_unchecked(i);
+        let (in_idx, out_idx) = indices(&token_in, &token_out);
+        let hop_in = amounts.get_unchecked(i);
+        let min_out = if i + 2 == path.len() { amount_out_min } else { 0 };
+
+        super::authorize_transfer(e, &token_in, &pool, hop_in);
+        let swap_args = vec![
+            e,
+            current_contract.to_val(),
+            in_idx.into_val(e),
+            out_idx.into_val(e),
+            (hop_in as u128).into_val(e),
+            (min_out as u128).into_val(e),
+        ];
+        let amount_out: u128 = invoke_pool(e, &pool, "swap", swap_args);
+        amounts.push_back(amount_out as i128);
+    }
+    super::forward(e, &path.last().unwrap_optimized(), to, amounts.last().unwrap_optimized());
+    amounts
+}
+
+/// Sells the quoted input for at least `amount_out`, so the hops can keep
+/// paying out to this contract
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let amounts_in = get_amounts_in(e, router, amount_out, path.clone());
+    let amount_in = amounts_in.get(0).unwrap_optimized();
+    if amount_in > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+
+    swap_exact_tokens_for_tokens(e, router, amount_in, amount_out, path, to, max_price_impact_bps)
+}
+
+pub fn get_amounts_out(
+    e: &Env,
+    router: &Address,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    quote_out(e, router, amount_in, &path).0
+}
+
+/// Share of the path's price selling `amount_in` along it keeps, scaled by
+/// `IMPACT_SCALAR`
+pub fn quote_price_kept(e: &Env, router: &Address, amount_in: i128, path: Vec<Address>) -> i128 {
+    quote_out(e, router, amount_in, &path).2
+}
+
+/// Each hop asks for one unit over the cheapest pool's reverse quote, which
+/// can round below what the forward swap needs
+pub fn get_amounts_in(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    try_get_amounts_in(e, router, amount_out, path)
+        .unwrap_or_else(|| panic_with_error!(e, LeverageError::InsufficientLiquidity))
+}
+
+/// `get_amounts_in`, or `None` if no pool can quote a hop, such as for more
+/// than it holds
+pub fn try_get_amounts_in(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Option<Vec<i128>> {
+    let mut amounts = vec![e, amount_out];
+    for i in (1..path.len()).rev() {
+        let token_in = path.get_unchecked(i - 1);
+        let token_out = path.get_unchecked(i);
+        let (in_idx, out_idx) = indices(&token_in, &token_out);
+        let hop_out = amounts.get_unchecked(0) as u128;
+
+        let mut best: Option<i128> = None;
+        for pool in pools_for(e, router, &token_in, &token_out).values() {
+            let quote: Option<u128> = try_invoke_pool(
+                e,
+                &pool,
+                "estimate_swap_strict_receive",
+                (in_idx, out_idx, hop_out).into_val(e),
+            );
+            match quote {
+                Some(hop_in) if hop_in > 0 => {
+                    let hop_in = hop_in as i128;
+                    if best.is_none_or(|best_in| hop_in < best_in) {
+                        best = Some(hop_in);
+                    }
+                }
+                _ => {}
+            }
+        }
+        amounts.push_front(best? + 1);
+    }
+    Some(amounts)
+}
+
+/// Quotes selling `amount_in` along `path`, returning the amounts, the pool
+/// each hop goes to and the share of the path's price the trade keeps,
+/// scaled by `IMPACT_SCALAR`
+///
+/// Each hop keeps `reserve_in / (reserve_in + amount_in)` of its price, from
+/// the chosen pool's reserves and leaving out the fee.
+fn quote_out(
+    e: &Env,
+    router: &Address,
+    amount_in: i128,
+    path: &Vec<Address>,
+) -> (Vec<i128>, Vec<Address>, i128) {
+    let mut amounts = vec![e, amount_in];
+    let mut pools: Vec<Address> = Vec::new(e);
+    let mut price_kept = IMPACT_SCALAR;
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let (in_idx, out_idx) = indices(&token_in, &token_out);
+        let hop_in = amounts.get_unchecked(i);
+
+        // Pool and what it pays for the hop
+        let mut best: Option<(Address, i128)> = None;
+        for pool in pools_for(e, router, &token_in, &token_out).values() {
+            let hop_out: u128 = invoke_pool(
+                e,
+                &pool,
+                "estimate_swap",
+                (in_idx, out_idx, hop_in as u128).into_val(e),
+            );
+            let hop_out = hop_out as i128;
+            if best.as_ref().is_none_or(|(_, best_out)| hop_out > *best_out) {
+                best = Some((pool, hop_out));
+            }
+        }
+        let (pool, hop_out) = best.unwrap_optimized();
+
+        let reserves: Vec<u128> = invoke_pool(e, &pool, "get_reserves", vec![e]);
+        let reserve_in = reserves.get_unchecked(in_idx) as i128;
+        price_kept = price_kept.fixed_mul_floor(e, &reserve_in, &(reserve_in + hop_in));
+
+        amounts.push_back(hop_out);
+        pools.push_back(pool);
+    }
+    (amounts, pools, price_kept)
+}
+
+/// Aqua orders a pool's tokens by address, so these are the indices of
+/// `token_in` and `token_out` in it
+fn indices(token_in: &Address, token_out: &Address) -> (u32, u32) {
+    if token_in < token_out {
+        (0, 1)
+    } else {
+        (1, 0)
+    }
+}
+
+/// Pools the router lists for `token_a` and `token_b`, failing with
+/// `PairNotFound` if there are none
+fn pools_for(
+    e: &Env,
+    router: &Address,
+    token_a: &Address,
+    token_b: &Address,
+) -> Map<BytesN<32>, Address> {
+    let tokens = if token_a < token_b {
+        vec![e, token_a.clone(), token_b.clone()]
+    } else {
+        vec![e, token_b.clone(), token_a.clone()]
+    };
+    let result = e.try_invoke_contract::<Map<BytesN<32>, Address>, InvokeError>(
+        router,
+        &Symbol::new(e, "get_pools"),
+        vec![e, tokens.to_val()],
+    );
+    match result {
+        Ok(Ok(pools)) if !pools.is_empty() => pools,
+        _ => panic_with_error!(e, LeverageError::PairNotFound),
+    }
+}
+
+/// Calls `fn_name` on an Aqua pool, failing with `SwapFailed` if it rejects
+/// the call
+fn invoke_pool<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    pool: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> T {
+    try_invoke_pool(e, pool, fn_name, args)
+        .unwrap_or_else(|| panic_with_error!(e, LeverageError::SwapFailed))
+}
+
+/// Calls `fn_name` on an Aqua pool, or returns `None` if it rejects the call
+fn try_invoke_pool<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    pool: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> Option<T> {
+    let result = e.try_invoke_contract::<T, InvokeError>(pool, &Symbol::new(e, fn_name), args);
+    match result {
+        Ok(Ok(value)) => Some(value),
+        _ => None,
+    }
+}
@@ -0,0 +1,277 @@
+use soroban_sdk::{
+    panic_with_error, vec, Address, Env, IntoVal, InvokeError, Symbol, TryFromVal, Val, Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::errors::LeverageError;
+use super::IMPACT_SCALAR;
+
+/// Fixed point scalar of Comet's weights and swap fee
+const STROOP: i128 = 10_000_000;
+/// Fixed point scalar the weighted pool math is worked out in
+const BONE: i128 = 1_000_000_000_000_000_000;
+/// Size of the last series term kept when raising to a fractional power
+const POW_PRECISION: i128 = BONE / 10_000_000_000;
+/// Shares of a balance Comet lets one swap put in, or take out
+const MAX_IN_RATIO: i128 = BONE / 2;
+const MAX_OUT_RATIO: i128 = BONE / 3;
+/// Comet's own math approximates powers too, so quotes are held this many
+/// parts in 10^8 to the cautious side of the exact result
+const QUOTE_MARGIN: i128 = 100_000_000;
+
+/// Balances, weights and fee a swap between two of the pool's tokens is
+/// priced with
+struct Hop {
+    balance_in: i128,
+    balance_out: i128,
+    weight_in: i128,
+    weight_out: i128,
+    fee: i128,
+}
+
+/// Comet sends the output back to the seller, so hops run one after another
+/// from this contract
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    pool: &Address,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let (amounts_out, price_kept) = quote_out(e, pool, amount_in, &path);
+    if amounts_out.last().unwrap_optimized() < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        super::require_price_kept(e, price_kept, max_price_impact_bps);
+    }
+
+    let current_contract = e.current_contract_address();
+    let mut amounts = vec![e, amount_in];
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let hop_in = amounts.get_unchecked(i);
+        let min_out = if i + 2 == path.len() { amount_out_min } else { 0 };
+
+        super::authorize_transfer(e, &token_in, pool, hop_in);
+        let swap_args = vec![
+            e,
+            token_in.to_val(),
+            hop_in.into_val(e),
+            token_out.to_val(),
+            min_out.into_val(e),
+            i128::MAX.into_val(e),
+            current_contract.to_val(),
+        ];
+        let (amount_out, _): (i128, i128) =
+            invoke_pool(e, pool, "swap_exact_amount_in", swap_args);
+        amounts.push_back(amount_out);
+    }
+    super::forward(e, &path.last().unwrap_optimized(), to, amounts.last().unwrap_optimized());
+    amounts
+}
+
+/// Sells the quoted input for at least `amount_out` rather than using
+/// Comet's exact output swap, whose input this contract would have to
+/// authorize to the unit
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    pool: &Address,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let amounts_in = get_amounts_in(e, pool, amount_out, path.clone());
+    let amount_in = amounts_in.get(0).unwrap_optimized();
+    if amount_in > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+
+    swap_exact_tokens_for_tokens(e, pool, amount_in, amount_out, path, to, max_price_impact_bps)
+}
+
+pub fn get_amounts_out(
+    e: &Env,
+    pool: &Address,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    quote_out(e, pool, amount_in, &path).0
+}
+
+/// Share of the path's price selling `amount_in` along it keeps, scaled by
+/// `IMPACT_SCALAR`
+pub fn quote_price_kept(e: &Env, pool: &Address, amount_in: i128, path: Vec<Address>) -> i128 {
+    quote_out(e, pool, amount_in, &path).1
+}
+
+pub fn get_amounts_in(
+    e: &Env,
+    pool: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    try_get_amounts_in(e, pool, amount_out, path)
+        .unwrap_or_else(|| panic_with_error!(e, LeverageError::InsufficientLiquidity))
+}
+
+/// `get_amounts_in`, or `None` if a hop would take out more of a balance
+/// than Comet allows
+pub fn try_get_amounts_in(
+    e: &Env,
+    pool: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Option<Vec<i128>> {
+    let mut amounts = vec![e, amount_out];
+    for i in (1..path.len()).rev() {
+        let hop = hop(e, pool, &path.get_unchecked(i - 1), &path.get_unchecked(i));
+        let amount_in = calc_in_given_out(e, &hop, amounts.get_unchecked(0))?;
+        amounts.push_front(amount_in);
+    }
+    Some(amounts)
+}
+
+/// Quotes selling `amount_in` along `path`, along with the share of the
+/// path's price the trade keeps, scaled by `IMPACT_SCALAR`
+///
+/// Each hop keeps its return over the return at the pool's spot price
+/// without the fee.
+fn quote_out(
+    e: &Env,
+    pool: &Address,
+    amount_in: i128,
+    path: &Vec<Address>,
+) -> (Vec<i128>, i128) {
+    let mut amounts = vec![e, amount_in];
+    let mut price_kept = IMPACT_SCALAR;
+    for i in 0..path.len() - 1 {
+        let hop = hop(e, pool, &path.get_unchecked(i), &path.get_unchecked(i + 1));
+        let hop_in = amounts.get_unchecked(i);
+        let hop_out = calc_out_given_in(e, &hop, hop_in);
+
+        // Spot price, out per in, is (balance_out / weight_out) /
+        // (balance_in / weight_in)
+        let in_after_fee = hop_in.fixed_mul_floor(e, &(STROOP - hop.fee), &STROOP);
+        if in_after_fee > 0 {
+            price_kept = price_kept
+                .fixed_mul_floor(e, &hop_out, &in_after_fee)
+                .fixed_mul_floor(
+                    e,
+                    &(hop.balance_in * hop.weight_out),
+                    &(hop.balance_out * hop.weight_in),
+                );
+        }
+        amounts.push_back(hop_out);
+    }
+    (amounts, price_kept.min(IMPACT_SCALAR))
+}
+
+/// Reads what pricing a swap from `token_in` to `token_out` needs, failing
+/// with `PairNotFound` unless the pool holds both
+fn hop(e: &Env, pool: &Address, token_in: &Address, token_out: &Address) -> Hop {
+    let tokens: Vec<Address> = invoke_pool(e, pool, "get_tokens", vec![e]);
+    if !tokens.contains(token_in) || !tokens.contains(token_out) {
+        panic_with_error!(e, LeverageError::PairNotFound);
+    }
+
+    Hop {
+        balance_in: invoke_pool(e, pool, "get_balance", (token_in.clone(),).into_val(e)),
+        balance_out: invoke_pool(e, pool, "get_balance", (token_out.clone(),).into_val(e)),
+        weight_in: invoke_pool(e, pool, "get_normalized_weight", (token_in.clone(),).into_val(e)),
+        weight_out: invoke_pool(e, pool, "get_normalized_weight", (token_out.clone(),).into_val(e)),
+        fee: invoke_pool(e, pool, "get_swap_fee", vec![e]),
+    }
+}
+
+/// `balance_out * (1 - (balance_in / (balance_in + amount_in * (1 - fee)))
+/// ^ (weight_in / weight_out))`, less the quote margin
+fn calc_out_given_in(e: &Env, hop: &Hop, amount_in: i128) -> i128 {
+    if amount_in.fixed_mul_floor(e, &BONE, &hop.balance_in) > MAX_IN_RATIO {
+        panic_with_error!(e, LeverageError::InsufficientLiquidity);
+    }
+
+    let in_after_fee = amount_in.fixed_mul_floor(e, &(STROOP - hop.fee), &STROOP);
+    let base = hop.balance_in.fixed_div_ceil(e, &(hop.balance_in + in_after_fee), &BONE);
+    let exponent = hop.weight_in.fixed_div_floor(e, &hop.weight_out, &BONE);
+    let kept = pow(e, base, exponent);
+    let amount_out = hop.balance_out.fixed_mul_floor(e, &(BONE - kept), &BONE);
+    (amount_out - amount_out / QUOTE_MARGIN - 1).max(0)
+}
+
+/// `balance_in * ((balance_out / (balance_out - amount_out))
+/// ^ (weight_out / weight_in) - 1) / (1 - fee)`, plus the quote margin, or
+/// `None` past Comet's limit on the output
+fn calc_in_given_out(e: &Env, hop: &Hop, amount_out: i128) -> Option<i128> {
+    if amount_out.fixed_mul_ceil(e, &BONE, &hop.balance_out) > MAX_OUT_RATIO {
+        return None;
+    }
+
+    let base = hop.balance_out.fixed_div_ceil(e, &(hop.balance_out - amount_out), &BONE);
+    let exponent = hop.weight_out.fixed_div_ceil(e, &hop.weight_in, &BONE);
+    let growth = pow(e, base, exponent) - BONE;
+    let amount_in = hop
+        .balance_in
+        .fixed_mul_ceil(e, &growth, &BONE)
+        .fixed_mul_ceil(e, &STROOP, &(STROOP - hop.fee));
+    Some(amount_in + amount_in / QUOTE_MARGIN + 1)
+}
+
+/// `base ^ exponent`, both scaled by `BONE`, for a base between 0 and 2
+///
+/// The whole part of the exponent is raised by squaring and the fractional
+/// part with the binomial series of `(1 + (base - 1)) ^ fraction`.
+fn pow(e: &Env, base: i128, exponent: i128) -> i128 {
+    let whole = exponent / BONE;
+    let fraction = exponent % BONE;
+
+    let mut result = BONE;
+    let mut square = base;
+    let mut n = whole;
+    while n > 0 {
+        if n % 2 == 1 {
+            result = result.fixed_mul_floor(e, &square, &BONE);
+        }
+        square = square.fixed_mul_floor(e, &square, &BONE);
+        n /= 2;
+    }
+    if fraction == 0 {
+        return result;
+    }
+
+    // Term k is term k-1 * (fraction - (k - 1)) * (base - 1) / k
+    let x = base - BONE;
+    let mut term = BONE;
+    let mut sum = BONE;
+    let mut k = 1;
+    while term.abs() >= POW_PRECISION {
+        let c = fraction - (k - 1) * BONE;
+        term = term
+            .fixed_mul_floor(e, &c, &BONE)
+            .fixed_mul_floor(e, &x, &BONE)
+            / k;
+        sum += term;
+        k += 1;
+    }
+    result.fixed_mul_floor(e, &sum, &BONE)
+}
+
+/// Calls `fn_name` on the Comet pool, failing with `SwapFailed` if it
+/// rejects the call
+fn invoke_pool<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    pool: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> T {
+    let result = e.try_invoke_contract::<T, InvokeError>(pool, &Symbol::new(e, fn_name), args);
+    match result {
+        Ok(Ok(value)) => value,
+        _ => panic_with_error!(e, LeverageError::SwapFailed),
+    }
+}
@@ -0,0 +1,628 @@
+use soroban_sdk::{
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    panic_with_error, token, vec, Address, Env, IntoVal, Symbol, Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::{
+    errors::LeverageError,
+    oracle,
+    storage::{
+        Config, Venue, get_compound_path, get_delever_routes, get_lever_path,
+        get_max_price_impact, get_venue,
+    },
+};
+
+mod aggregator;
+mod aqua;
+mod comet;
+mod phoenix;
+mod soroswap;
+
+/// Fixed point scalar price impacts are worked out in
+const IMPACT_SCALAR: i128 = 10_000_000;
+
+/// Times `max_amount_in` halves its search for the largest amount within
+/// the price impact limit, leaving it within 1/4096 of the amount
+const IMPACT_SEARCH_STEPS: u32 = 12;
+
+/// Slices a split swap is cut into, each going to the route that pays most
+/// for it
+const SPLIT_SLICES: i128 = 5;
+
+/// Slippage tolerance and deadline for the swaps of one call
+#[derive(Clone)]
+pub struct SwapLimits {
+    /// Largest shortfall against the oracle price accepted, in bps
+    pub max_slippage_bps: i128,
+    /// Ledger timestamp after which the router rejects the swap
+    pub deadline: u64,
+}
+
+/// Path debt is sold along for collateral, the direct pair unless the owner
+/// set one
+pub fn lever_path(e: &Env, config: &Config) -> Vec<Address> {
+    get_lever_path(e).unwrap_or_else(|| {
+        vec![e, config.debt_asset.clone(), config.collateral_asset.clone()]
+    })
+}
+
+/// Path rewards are sold along for collateral, the direct pair unless the
+/// owner set one
+pub fn compound_path(e: &Env, config: &Config) -> Vec<Address> {
+    get_compound_path(e).unwrap_or_else(|| {
+        vec![e, config.reward_token.clone(), config.collateral_asset.clone()]
+    })
+}
+
+/// Routes collateral is sold along for debt, split between when there are
+/// several. Just the direct pair unless the owner set routes.
+pub fn delever_routes(e: &Env, config: &Config) -> Vec<Vec<Address>> {
+    get_delever_routes(e).unwrap_or_else(|| {
+        vec![e, vec![e, config.collateral_asset.clone(), config.debt_asset.clone()]]
+    })
+}
+
+/// Sells exactly `amount_in` split across `routes` for at least
+/// `amount_out_min` in total, returning the amount bought
+///
+/// The legs are sized by `split_amount_in`. Every route is quoted on its
+/// own, so routes should not share pairs.
+pub fn swap_exact_tokens_split(
+    e: &Env,
+    config: &Config,
+    amount_in: i128,
+    amount_out_min: i128,
+    routes: &Vec<Vec<Address>>,
+    to: &Address,
+    deadline: u64,
+) -> i128 {
+    if routes.len() == 1 {
+        let path = routes.get(0).unwrap_optimized();
+        let amounts = swap_exact_tokens_for_tokens(
+            e,
+            config,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            deadline,
+        );
+        return amounts.last().unwrap_optimized();
+    }
+
+    let (legs_in, _) = split_amount_in(e, config, amount_in, routes);
+
+    // Each leg is held to the combined minimum below
+    let mut amount_out = 0;
+    for (i, path) in routes.iter().enumerate() {
+        let leg_in = legs_in.get_unchecked(i as u32);
+        if leg_in > 0 {
+            let amounts = swap_exact_tokens_for_tokens(e, config, leg_in, 0, path, to, deadline);
+            amount_out += amounts.last().unwrap_optimized();
+        }
+    }
+    if amount_out < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    amount_out
+}
+
+/// Amounts in and out of every route when selling `amount_in` across them
+///
+/// The amount is cut into slices and each slice goes to the route whose
+/// quote grows most from it on top of what the route already carries.
+fn split_amount_in(
+    e: &Env,
+    config: &Config,
+    amount_in: i128,
+    routes: &Vec<Vec<Address>>,
+) -> (Vec<i128>, Vec<i128>) {
+    let mut legs_in: Vec<i128> = Vec::new(e);
+    let mut legs_out: Vec<i128> = Vec::new(e);
+    for _ in routes.iter() {
+        legs_in.push_back(0);
+        legs_out.push_back(0);
+    }
+    let mut allocated = 0;
+    for slice in 0..SPLIT_SLICES {
+        let size = if slice == SPLIT_SLICES - 1 {
+            amount_in - allocated
+        } else {
+            amount_in / SPLIT_SLICES
+        };
+        if size <= 0 {
+            continue;
+        }
+
+        // Route index, its quote with the slice and what the slice adds
+        let mut best: Option<(u32, i128, i128)> = None;
+        for (i, path) in routes.iter().enumerate() {
+            let i = i as u32;
+            let leg_in = legs_in.get_unchecked(i) + size;
+            let leg_out = get_amounts_out(e, config, leg_in, path).last().unwrap_optimized();
+            let gain = leg_out - legs_out.get_unchecked(i);
+            if best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                best = Some((i, leg_out, gain));
+            }
+        }
+        let (i, leg_out, _) = best.unwrap_optimized();
+        legs_in.set(i, legs_in.get_unchecked(i) + size);
+        legs_out.set(i, leg_out);
+        allocated += size;
+    }
+    (legs_in, legs_out)
+}
+
+/// Largest part of `amount_in` that selling across `routes` keeps within
+/// the owner's price impact limit, so callers running in rounds can sell
+/// the rest in later ones
+///
+/// Price impact only grows with the amount, so this halves the gap between
+/// an amount within the limit and one past it `IMPACT_SEARCH_STEPS` times.
+/// Returns zero if even a single unit moves the price too far.
+pub fn max_amount_in(
+    e: &Env,
+    config: &Config,
+    amount_in: i128,
+    routes: &Vec<Vec<Address>>,
+) -> i128 {
+    let Some(max_price_impact_bps) = get_max_price_impact(e) else {
+        return amount_in;
+    };
+    let within_limit = |amount: i128| {
+        let legs_in = if routes.len() == 1 {
+            vec![e, amount]
+        } else {
+            split_amount_in(e, config, amount, routes).0
+        };
+        routes.iter().zip(legs_in.iter()).all(|(path, leg_in)| {
+            leg_in <= 0
+                || price_impact_bps(e, quote_price_kept(e, config, leg_in, path))
+                    <= max_price_impact_bps
+        })
+    };
+    if amount_in <= 0 || within_limit(amount_in) {
+        return amount_in;
+    }
+
+    let (mut low, mut high) = (0, amount_in);
+    for _ in 0..IMPACT_SEARCH_STEPS {
+        let mid = low + (high - low) / 2;
+        if mid == low {
+            break;
+        }
+        if within_limit(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Buys exactly `amount_out` split across `routes` for at most
+/// `amount_in_max` in total, returning the amounts sold and bought
+///
+/// The legs are sized by `get_amount_in_split`. Venues without an exact
+/// output swap may buy a little more than `amount_out`, so callers should
+/// book the amount bought rather than `amount_out`.
+pub fn swap_tokens_for_exact_tokens_split(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    amount_in_max: i128,
+    routes: &Vec<Vec<Address>>,
+    to: &Address,
+    deadline: u64,
+) -> (i128, i128) {
+    if routes.len() == 1 {
+        let path = routes.get(0).unwrap_optimized();
+        let amounts = swap_tokens_for_exact_tokens(
+            e,
+            config,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            deadline,
+        );
+        return (amounts.get(0).unwrap_optimized(), amounts.last().unwrap_optimized());
+    }
+
+    let (legs_in, legs_out) = split_amount_out(e, config, amount_out, routes);
+    if legs_in.iter().sum::<i128>() > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    let mut amount_in = 0;
+    let mut bought = 0;
+    for (i, path) in routes.iter().enumerate() {
+        let leg_out = legs_out.get_unchecked(i as u32);
+        if leg_out > 0 {
+            let leg_in = legs_in.get_unchecked(i as u32);
+            let amounts =
+                swap_tokens_for_exact_tokens(e, config, leg_out, leg_in, path, to, deadline);
+            amount_in += amounts.get(0).unwrap_optimized();
+            bought += amounts.last().unwrap_optimized();
+        }
+    }
+    (amount_in, bought)
+}
+
+/// Quotes the input needed to buy `amount_out` split across `routes`, the
+/// way `swap_tokens_for_exact_tokens_split` buys it
+pub fn get_amount_in_split(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    routes: &Vec<Vec<Address>>,
+) -> i128 {
+    if routes.len() == 1 {
+        let path = routes.get(0).unwrap_optimized();
+        return get_amounts_in(e, config, amount_out, path).get(0).unwrap_optimized();
+    }
+    split_amount_out(e, config, amount_out, routes).0.iter().sum()
+}
+
+/// Amounts in and out of every route when buying `amount_out` across them
+///
+/// The amount is cut into slices and each slice goes to the route whose
+/// quoted input grows least from it on top of what the route already
+/// carries. Routes that cannot quote a slice, such as for more than they
+/// hold, are passed over for it.
+fn split_amount_out(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    routes: &Vec<Vec<Address>>,
+) -> (Vec<i128>, Vec<i128>) {
+    let mut legs_in: Vec<i128> = Vec::new(e);
+    let mut legs_out: Vec<i128> = Vec::new(e);
+    for _ in routes.iter() {
+        legs_in.push_back(0);
+        legs_out.push_back(0);
+    }
+    let mut allocated = 0;
+    for slice in 0..SPLIT_SLICES {
+        let size = if slice == SPLIT_SLICES - 1 {
+            amount_out - allocated
+        } else {
+            amount_out / SPLIT_SLICES
+        };
+        if size <= 0 {
+            continue;
+        }
+
+        // Route index, its quote with the slice and what the slice costs
+        let mut best: Option<(u32, i128, i128)> = None;
+        for (i, path) in routes.iter().enumerate() {
+            let i = i as u32;
+            let leg_out = legs_out.get_unchecked(i) + size;
+            let Some(amounts) = try_get_amounts_in(e, config, leg_out, path) else {
+                continue;
+            };
+            let leg_in = amounts.get(0).unwrap_optimized();
+            let cost = leg_in - legs_in.get_unchecked(i);
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((i, leg_in, cost));
+            }
+        }
+        let Some((i, leg_in, _)) = best else {
+            panic_with_error!(e, LeverageError::InsufficientLiquidity);
+        };
+        legs_in.set(i, leg_in);
+        legs_out.set(i, legs_out.get_unchecked(i) + size);
+        allocated += size;
+    }
+    (legs_in, legs_out)
+}
+
+/// Swaps exact amount of input tokens for a minimum amount of output tokens
+///
+/// This is a simplified version matching the blend strategy implementation
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    config: &Config,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+) -> Vec<i128> {
+    require_before(e, deadline);
+    let max_price_impact_bps = get_max_price_impact(e);
+    match get_venue(e) {
+        Venue::SoroswapRouter => soroswap::swap_exact_tokens_for_tokens(
+            e,
+            &config.swap_router,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Phoenix(factory) => phoenix::swap_exact_tokens_for_tokens(
+            e,
+            &factory,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Comet(pool) => comet::swap_exact_tokens_for_tokens(
+            e,
+            &pool,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            max_price_impact_bps,
+        ),
+        Venue::SoroswapAggregator(aggregator) => aggregator::swap_exact_tokens_for_tokens(
+            e,
+            &aggregator,
+            &config.swap_router,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Aqua(router) => aqua::swap_exact_tokens_for_tokens(
+            e,
+            &router,
+            amount_in,
+            amount_out_min,
+            path,
+            to,
+            max_price_impact_bps,
+        ),
+    }
+}
+
+/// Swaps as few input tokens as needed, up to `amount_in_max`, for an exact
+/// amount of output tokens
+///
+/// Venues without an exact output swap sell their quoted input instead and
+/// may return a little more than `amount_out`.
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+) -> Vec<i128> {
+    require_before(e, deadline);
+    let max_price_impact_bps = get_max_price_impact(e);
+    match get_venue(e) {
+        Venue::SoroswapRouter => soroswap::swap_tokens_for_exact_tokens(
+            e,
+            &config.swap_router,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Phoenix(factory) => phoenix::swap_tokens_for_exact_tokens(
+            e,
+            &factory,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Comet(pool) => comet::swap_tokens_for_exact_tokens(
+            e,
+            &pool,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            max_price_impact_bps,
+        ),
+        Venue::SoroswapAggregator(aggregator) => aggregator::swap_tokens_for_exact_tokens(
+            e,
+            &aggregator,
+            &config.swap_router,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            deadline,
+            max_price_impact_bps,
+        ),
+        Venue::Aqua(router) => aqua::swap_tokens_for_exact_tokens(
+            e,
+            &router,
+            amount_out,
+            amount_in_max,
+            path,
+            to,
+            max_price_impact_bps,
+        ),
+    }
+}
+
+fn require_before(e: &Env, deadline: u64) {
+    if deadline < e.ledger().timestamp() {
+        panic_with_error!(e, LeverageError::DeadlineExpired);
+    }
+}
+
+/// Helper to get the expected output amount for a given input
+pub fn get_amounts_out(
+    e: &Env,
+    config: &Config,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    match get_venue(e) {
+        Venue::SoroswapRouter | Venue::SoroswapAggregator(_) => {
+            soroswap::get_amounts_out(e, &config.swap_router, amount_in, path)
+        }
+        Venue::Phoenix(factory) => phoenix::get_amounts_out(e, &factory, amount_in, path),
+        Venue::Comet(pool) => comet::get_amounts_out(e, &pool, amount_in, path),
+        Venue::Aqua(router) => aqua::get_amounts_out(e, &router, amount_in, path),
+    }
+}
+
+/// Helper to get the required input amount for a desired output
+pub fn get_amounts_in(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    match get_venue(e) {
+        Venue::SoroswapRouter | Venue::SoroswapAggregator(_) => {
+            soroswap::get_amounts_in(e, &config.swap_router, amount_out, path)
+        }
+        Venue::Phoenix(factory) => phoenix::get_amounts_in(e, &factory, amount_out, path),
+        Venue::Comet(pool) => comet::get_amounts_in(e, &pool, amount_out, path),
+        Venue::Aqua(router) => aqua::get_amounts_in(e, &router, amount_out, path),
+    }
+}
+
+/// `get_amounts_in`, or `None` if the venue cannot quote it
+fn try_get_amounts_in(
+    e: &Env,
+    config: &Config,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Option<Vec<i128>> {
+    match get_venue(e) {
+        Venue::SoroswapRouter | Venue::SoroswapAggregator(_) => {
+            soroswap::try_get_amounts_in(e, &config.swap_router, amount_out, path)
+        }
+        Venue::Phoenix(factory) => phoenix::try_get_amounts_in(e, &factory, amount_out, path),
+        Venue::Comet(pool) => comet::try_get_amounts_in(e, &pool, amount_out, path),
+        Venue::Aqua(router) => aqua::try_get_amounts_in(e, &router, amount_out, path),
+    }
+}
+
+/// Share of the path's price selling `amount_in` along it keeps, scaled by
+/// `IMPACT_SCALAR`
+fn quote_price_kept(e: &Env, config: &Config, amount_in: i128, path: Vec<Address>) -> i128 {
+    match get_venue(e) {
+        Venue::SoroswapRouter | Venue::SoroswapAggregator(_) => {
+            soroswap::quote_price_kept(e, &config.swap_router, amount_in, path)
+        }
+        Venue::Phoenix(factory) => phoenix::quote_price_kept(e, &factory, amount_in, path),
+        Venue::Comet(pool) => comet::quote_price_kept(e, &pool, amount_in, path),
+        Venue::Aqua(router) => aqua::quote_price_kept(e, &router, amount_in, path),
+    }
+}
+
+/// Least amount of the last token in `path` that swapping `amount_in` of the
+/// first may return: its value at the oracle's prices, less
+/// `max_deviation_bps`
+///
+/// Every swap is held to this, since the router's own quote is controlled by
+/// whoever can move its pairs.
+pub fn oracle_min_amount_out(
+    e: &Env,
+    config: &Config,
+    path: &Vec<Address>,
+    amount_in: i128,
+    max_deviation_bps: i128,
+) -> i128 {
+    let token_in = path.first().unwrap_optimized();
+    let token_out = path.last().unwrap_optimized();
+    let value_in = amount_in.fixed_mul_floor(
+        e,
+        &oracle::get_price(e, config, &token_in),
+        &token_scalar(e, &token_in),
+    );
+    calculate_min_amount_out(value_in, max_deviation_bps).fixed_mul_ceil(
+        e,
+        &token_scalar(e, &token_out),
+        &oracle::get_price(e, config, &token_out),
+    )
+}
+
+/// Panics with `SlippageExceeded` if swapping `amount_in` of the first token
+/// in `path` for `amount_out` of the last returns less than
+/// `oracle_min_amount_out`
+pub fn require_oracle_price(
+    e: &Env,
+    config: &Config,
+    path: &Vec<Address>,
+    amount_in: i128,
+    amount_out: i128,
+    max_deviation_bps: i128,
+) {
+    if amount_out < oracle_min_amount_out(e, config, path, amount_in, max_deviation_bps) {
+        panic_with_error!(e, LeverageError::SlippageExceeded);
+    }
+}
+
+/// Panics with `PriceImpactExceeded` if a trade keeping `price_kept` (scaled
+/// by `IMPACT_SCALAR`) of its path's price moves it by more than
+/// `max_price_impact_bps`
+fn require_price_kept(e: &Env, price_kept: i128, max_price_impact_bps: i128) {
+    if price_impact_bps(e, price_kept) > max_price_impact_bps {
+        panic_with_error!(e, LeverageError::PriceImpactExceeded);
+    }
+}
+
+/// Price impact, in bps, of a trade keeping `price_kept` of its path's price
+fn price_impact_bps(e: &Env, price_kept: i128) -> i128 {
+    (IMPACT_SCALAR - price_kept).fixed_mul_ceil(e, &10_000, &IMPACT_SCALAR)
+}
+
+/// Authorizes `to` to pull `amount` of `token` from this contract during the
+/// next call, the way each venue takes the input of a swap
+fn authorize_transfer(e: &Env, token: &Address, to: &Address, amount: i128) {
+    e.authorize_as_current_contract(vec![
+        e,
+        InvokerContractAuthEntry::Contract(SubContractInvocation {
+            context: ContractContext {
+                contract: token.clone(),
+                fn_name: Symbol::new(e, "transfer"),
+                args: (e.current_contract_address(), to.clone(), amount).into_val(e),
+            },
+            sub_invocations: vec![e],
+        }),
+    ]);
+}
+
+/// Sends `amount` of `token` on to `to` if a venue paid it to this contract
+fn forward(e: &Env, token: &Address, to: &Address, amount: i128) {
+    let current_contract = e.current_contract_address();
+    if *to != current_contract && amount > 0 {
+        token::Client::new(e, token).transfer(&current_contract, to, &amount);
+    }
+}
+
+/// Fixed point scalar of `token`'s amounts
+fn token_scalar(e: &Env, token: &Address) -> i128 {
+    10i128.pow(token::TokenClient::new(e, token).decimals())
+}
+
+/// Calculate minimum output with slippage protection
+pub fn calculate_min_amount_out(
+    amount_out_expected: i128,
+    slippage_bps: i128,
+) -> i128 {
+    let slippage_factor = 10000 - slippage_bps;
+    (amount_out_expected * slippage_factor) / 10000
+}
+
+/// Calculate maximum input with slippage protection
+pub fn calculate_max_amount_in(
+    amount_in_expected: i128,
+    slippage_bps: i128,
+) -> i128 {
+    let slippage_factor = 10000 + slippage_bps;
+    (amount_in_expected * slippage_factor) / 10000
+}
\ No newline at end of file
@@ -0,0 +1,241 @@
+use soroban_sdk::{
+    contracttype, panic_with_error, vec, Address, Env, IntoVal, InvokeError, Symbol, TryFromVal,
+    Val, Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::errors::LeverageError;
+use super::IMPACT_SCALAR;
+
+/// Phoenix pool `simulate_swap` result
+#[derive(Clone)]
+#[contracttype]
+pub struct SimulateSwapResponse {
+    pub ask_amount: i128,
+    pub commission_amount: i128,
+    pub spread_amount: i128,
+    pub total_return: i128,
+}
+
+/// Phoenix pool `simulate_reverse_swap` result
+#[derive(Clone)]
+#[contracttype]
+pub struct SimulateReverseSwapResponse {
+    pub offer_amount: i128,
+    pub commission_amount: i128,
+    pub spread_amount: i128,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    factory: &Address,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let (amounts_out, price_kept) = simulate(e, factory, amount_in, &path);
+    if amounts_out.last().unwrap_optimized() < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        super::require_price_kept(e, price_kept, max_price_impact_bps);
+    }
+
+    // Each hop pays out to this contract, which sells it on in the next
+    let current_contract = e.current_contract_address();
+    let mut amounts = vec![e, amount_in];
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let pool = pool_for(e, factory, &token_in, &token_out);
+        let offer_amount = amounts.get_unchecked(i);
+        let ask_min = if i + 2 == path.len() { Some(amount_out_min) } else { None };
+
+        super::authorize_transfer(e, &token_in, &pool, offer_amount);
+        let swap_args = vec![
+            e,
+            current_contract.to_val(),
+            token_in.to_val(),
+            offer_amount.into_val(e),
+            ask_min.into_val(e),
+            Option::<i64>::None.into_val(e),
+            Some(deadline).into_val(e),
+            Option::<i64>::None.into_val(e),
+        ];
+        let amount_out: i128 = invoke_pool(e, &pool, "swap", swap_args);
+        amounts.push_back(amount_out);
+    }
+    super::forward(e, &path.last().unwrap_optimized(), to, amounts.last().unwrap_optimized());
+    amounts
+}
+
+/// Phoenix pools only swap exact inputs, so this sells the quoted input for
+/// at least `amount_out`
+#[allow(clippy::too_many_arguments)]
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    factory: &Address,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let amounts_in = get_amounts_in(e, factory, amount_out, path.clone());
+    let amount_in = amounts_in.get(0).unwrap_optimized();
+    if amount_in > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+
+    swap_exact_tokens_for_tokens(
+        e,
+        factory,
+        amount_in,
+        amount_out,
+        path,
+        to,
+        deadline,
+        max_price_impact_bps,
+    )
+}
+
+pub fn get_amounts_out(
+    e: &Env,
+    factory: &Address,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    simulate(e, factory, amount_in, &path).0
+}
+
+/// Share of the path's price selling `amount_in` along it keeps, scaled by
+/// `IMPACT_SCALAR`
+pub fn quote_price_kept(
+    e: &Env,
+    factory: &Address,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> i128 {
+    simulate(e, factory, amount_in, &path).1
+}
+
+/// Each hop asks for one unit over the pool's reverse quote, which can round
+/// below what the forward swap needs
+pub fn get_amounts_in(
+    e: &Env,
+    factory: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    try_get_amounts_in(e, factory, amount_out, path)
+        .unwrap_or_else(|| panic_with_error!(e, LeverageError::InsufficientLiquidity))
+}
+
+/// `get_amounts_in`, or `None` if a pool cannot quote its hop, such as for
+/// more than it holds
+pub fn try_get_amounts_in(
+    e: &Env,
+    factory: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Option<Vec<i128>> {
+    let mut amounts = vec![e, amount_out];
+    for i in (1..path.len()).rev() {
+        let token_in = path.get_unchecked(i - 1);
+        let token_out = path.get_unchecked(i);
+        let pool = pool_for(e, factory, &token_in, &token_out);
+        let response: SimulateReverseSwapResponse = try_invoke_pool(
+            e,
+            &pool,
+            "simulate_reverse_swap",
+            (token_out, amounts.get_unchecked(0)).into_val(e),
+        )?;
+        if response.offer_amount <= 0 {
+            return None;
+        }
+        amounts.push_front(response.offer_amount + 1);
+    }
+    Some(amounts)
+}
+
+/// Quotes selling `amount_in` along `path`, along with the share of the
+/// path's price the trade keeps, scaled by `IMPACT_SCALAR`
+///
+/// Each hop keeps its return over the return it would have at the pool's
+/// spot price, both before commission.
+fn simulate(
+    e: &Env,
+    factory: &Address,
+    amount_in: i128,
+    path: &Vec<Address>,
+) -> (Vec<i128>, i128) {
+    let mut amounts = vec![e, amount_in];
+    let mut price_kept = IMPACT_SCALAR;
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let pool = pool_for(e, factory, &token_in, &token_out);
+        let response: SimulateSwapResponse = invoke_pool(
+            e,
+            &pool,
+            "simulate_swap",
+            (token_in, amounts.get_unchecked(i)).into_val(e),
+        );
+
+        let return_amount = response.ask_amount + response.commission_amount;
+        if return_amount > 0 {
+            price_kept = price_kept.fixed_mul_floor(
+                e,
+                &return_amount,
+                &(return_amount + response.spread_amount),
+            );
+        }
+        amounts.push_back(response.ask_amount);
+    }
+    (amounts, price_kept)
+}
+
+/// Phoenix pool trading `token_a` against `token_b`
+fn pool_for(e: &Env, factory: &Address, token_a: &Address, token_b: &Address) -> Address {
+    let result = e.try_invoke_contract::<Address, InvokeError>(
+        factory,
+        &Symbol::new(e, "query_for_pool_by_token_pair"),
+        (token_a.clone(), token_b.clone()).into_val(e),
+    );
+    match result {
+        Ok(Ok(pool)) => pool,
+        _ => panic_with_error!(e, LeverageError::PairNotFound),
+    }
+}
+
+/// Calls `fn_name` on a Phoenix pool, failing with `SwapFailed` if it
+/// rejects the call
+fn invoke_pool<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    pool: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> T {
+    try_invoke_pool(e, pool, fn_name, args)
+        .unwrap_or_else(|| panic_with_error!(e, LeverageError::SwapFailed))
+}
+
+/// Calls `fn_name` on a Phoenix pool, or returns `None` if it rejects the
+/// call
+fn try_invoke_pool<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    pool: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> Option<T> {
+    let result = e.try_invoke_contract::<T, InvokeError>(pool, &Symbol::new(e, fn_name), args);
+    match result {
+        Ok(Ok(value)) => Some(value),
+        _ => None,
+    }
+}
@@ -0,0 +1,215 @@
+use soroban_sdk::{
+    panic_with_error, vec, Address, Env, IntoVal, InvokeError, Symbol, TryFromVal, Val, Vec,
+};
+use soroban_sdk::unwrap::UnwrapOptimized;
+use soroban_fixed_point_math::SorobanFixedPoint;
+use crate::errors::LeverageError;
+use super::IMPACT_SCALAR;
+
+/// Soroswap router and library error codes the contract translates
+const LIBRARY_INSUFFICIENT_LIQUIDITY: u32 = 302;
+const ROUTER_DEADLINE_EXPIRED: u32 = 403;
+const ROUTER_INSUFFICIENT_OUTPUT_AMOUNT: u32 = 407;
+const ROUTER_EXCESSIVE_INPUT_AMOUNT: u32 = 408;
+const ROUTER_PAIR_DOES_NOT_EXIST: u32 = 409;
+
+#[allow(clippy::too_many_arguments)]
+pub fn swap_exact_tokens_for_tokens(
+    e: &Env,
+    router: &Address,
+    amount_in: i128,
+    amount_out_min: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    let amounts_out = get_amounts_out(e, router, amount_in, path.clone());
+    if amounts_out.last().unwrap_optimized() < amount_out_min {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        require_price_impact(e, router, &path, &amounts_out, max_price_impact_bps);
+    }
+
+    let swap_args = vec![
+        e,
+        amount_in.into_val(e),
+        amount_out_min.into_val(e),
+        path.into_val(e),
+        to.to_val(),
+        deadline.into_val(e),
+    ];
+
+    authorize_pair_transfer(e, router, &path, amount_in);
+
+    // Execute swap
+    invoke_router(e, router, "swap_exact_tokens_for_tokens", swap_args)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn swap_tokens_for_exact_tokens(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    amount_in_max: i128,
+    path: Vec<Address>,
+    to: &Address,
+    deadline: u64,
+    max_price_impact_bps: Option<i128>,
+) -> Vec<i128> {
+    // The router pulls exactly what it quotes for the output
+    let amounts_in = get_amounts_in(e, router, amount_out, path.clone());
+    let amount_in = amounts_in.get(0).unwrap_optimized();
+    if amount_in > amount_in_max {
+        panic_with_error!(e, LeverageError::InsufficientSwapOutput);
+    }
+    if let Some(max_price_impact_bps) = max_price_impact_bps {
+        require_price_impact(e, router, &path, &amounts_in, max_price_impact_bps);
+    }
+
+    let swap_args = vec![
+        e,
+        amount_out.into_val(e),
+        amount_in_max.into_val(e),
+        path.into_val(e),
+        to.to_val(),
+        deadline.into_val(e),
+    ];
+
+    authorize_pair_transfer(e, router, &path, amount_in);
+
+    invoke_router(e, router, "swap_tokens_for_exact_tokens", swap_args)
+}
+
+pub fn get_amounts_out(
+    e: &Env,
+    router: &Address,
+    amount_in: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    invoke_router(e, router, "router_get_amounts_out", (amount_in, path).into_val(e))
+}
+
+pub fn get_amounts_in(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Vec<i128> {
+    invoke_router(e, router, "router_get_amounts_in", (amount_out, path).into_val(e))
+}
+
+/// `get_amounts_in`, or `None` if the router cannot quote it, such as for
+/// more than a pair holds
+pub fn try_get_amounts_in(
+    e: &Env,
+    router: &Address,
+    amount_out: i128,
+    path: Vec<Address>,
+) -> Option<Vec<i128>> {
+    let result = e.try_invoke_contract::<Vec<i128>, InvokeError>(
+        router,
+        &Symbol::new(e, "router_get_amounts_in"),
+        (amount_out, path).into_val(e),
+    );
+    match result {
+        Ok(Ok(amounts)) => Some(amounts),
+        _ => None,
+    }
+}
+
+/// Panics with `PriceImpactExceeded` if putting `amounts[i]` into the pair
+/// for hop `i` of `path` moves the price along the whole path by more than
+/// `max_price_impact_bps`
+pub fn require_price_impact(
+    e: &Env,
+    router: &Address,
+    path: &Vec<Address>,
+    amounts: &Vec<i128>,
+    max_price_impact_bps: i128,
+) {
+    let price_kept = price_kept(e, router, path, amounts);
+    super::require_price_kept(e, price_kept, max_price_impact_bps);
+}
+
+/// Share of the path's price selling `amount_in` along it keeps, scaled by
+/// `IMPACT_SCALAR`
+pub fn quote_price_kept(e: &Env, router: &Address, amount_in: i128, path: Vec<Address>) -> i128 {
+    let amounts_out = get_amounts_out(e, router, amount_in, path.clone());
+    price_kept(e, router, &path, &amounts_out)
+}
+
+/// Share of the path's price putting `amounts[i]` into the pair for hop `i`
+/// keeps, scaled by `IMPACT_SCALAR`
+///
+/// Each hop keeps `reserve_in / (reserve_in + amount_in)` of its price, from
+/// the pair's reserves before the swap and leaving out the fee.
+fn price_kept(e: &Env, router: &Address, path: &Vec<Address>, amounts: &Vec<i128>) -> i128 {
+    let mut price_kept = IMPACT_SCALAR;
+    for i in 0..path.len() - 1 {
+        let token_in = path.get_unchecked(i);
+        let token_out = path.get_unchecked(i + 1);
+        let pair: Address =
+            invoke_router(e, router, "router_pair_for", (token_in.clone(), token_out).into_val(e));
+        let (reserve_0, reserve_1): (i128, i128) =
+            e.invoke_contract(&pair, &Symbol::new(e, "get_reserves"), vec![e]);
+        let token_0: Address = e.invoke_contract(&pair, &Symbol::new(e, "token_0"), vec![e]);
+        let reserve_in = if token_0 == token_in { reserve_0 } else { reserve_1 };
+
+        let amount_in = amounts.get_unchecked(i);
+        price_kept = price_kept.fixed_mul_floor(e, &reserve_in, &(reserve_in + amount_in));
+    }
+    price_kept
+}
+
+/// Calls `fn_name` on the router, translating a Soroswap error into the
+/// matching `LeverageError`
+fn invoke_router<T: TryFromVal<Env, Val>>(
+    e: &Env,
+    router: &Address,
+    fn_name: &str,
+    args: Vec<Val>,
+) -> T {
+    let result = e.try_invoke_contract::<T, InvokeError>(
+        router,
+        &Symbol::new(e, fn_name),
+        args,
+    );
+    let code = match result {
+        Ok(Ok(value)) => return value,
+        Err(Ok(InvokeError::Contract(code))) => code,
+        _ => panic_with_error!(e, LeverageError::SwapFailed),
+    };
+    let error = match code {
+        LIBRARY_INSUFFICIENT_LIQUIDITY => LeverageError::InsufficientLiquidity,
+        ROUTER_DEADLINE_EXPIRED => LeverageError::DeadlineExpired,
+        ROUTER_INSUFFICIENT_OUTPUT_AMOUNT | ROUTER_EXCESSIVE_INPUT_AMOUNT => {
+            LeverageError::InsufficientSwapOutput
+        }
+        ROUTER_PAIR_DOES_NOT_EXIST => LeverageError::PairNotFound,
+        _ => LeverageError::SwapFailed,
+    };
+    panic_with_error!(e, error)
+}
+
+/// Authorizes the router to move `amount` of the first token in `path` from
+/// this contract into the first pair
+///
+/// Later hops move tokens from one pair straight into the next, so the first
+/// transfer is the only one this contract signs whatever the path length.
+fn authorize_pair_transfer(
+    e: &Env,
+    router: &Address,
+    path: &Vec<Address>,
+    amount: i128,
+) {
+    let pair_address = first_pair(e, router, path);
+    super::authorize_transfer(e, &path.get(0).unwrap_optimized(), &pair_address, amount);
+}
+
+/// The pair the router sends the first token in `path` to
+pub fn first_pair(e: &Env, router: &Address, path: &Vec<Address>) -> Address {
+    let pair_args = (path.get(0).unwrap_optimized(), path.get(1).unwrap_optimized());
+    invoke_router(e, router, "router_pair_for", pair_args.into_val(e))
+}
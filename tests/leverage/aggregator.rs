@@ -0,0 +1,149 @@
+use soroban_sdk::{
+    contract, contractimpl, contracttype, panic_with_error, vec, Address, BytesN, Env, Error, Vec,
+};
+
+use super::router::MockRouterClient;
+
+/// Error the mock aggregator fails with when the distributions return less
+/// than the minimum, or take more than the maximum
+pub const SLIPPAGE_EXCEEDED: u32 = 1;
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum Protocol {
+    Soroswap = 0,
+    Phoenix = 1,
+    Aqua = 2,
+    Comet = 3,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DexDistribution {
+    pub protocol_id: Protocol,
+    pub path: Vec<Address>,
+    pub parts: u32,
+    pub bytes: Option<Vec<BytesN<32>>>,
+}
+
+#[contracttype]
+enum AggregatorKey {
+    Router,
+    Swaps,
+}
+
+/// Soroswap aggregator splitting a swap by parts across distributions, of
+/// which it only trades Soroswap's, through `MockRouter`
+#[contract]
+pub struct MockAggregator;
+
+#[contractimpl]
+impl MockAggregator {
+    pub fn __constructor(e: Env, router: Address) {
+        e.storage().instance().set(&AggregatorKey::Router, &router);
+    }
+
+    /// Number of swaps so far
+    pub fn swaps(e: Env) -> u32 {
+        e.storage().instance().get(&AggregatorKey::Swaps).unwrap_or(0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_exact_tokens_for_tokens(
+        e: Env,
+        token_in: Address,
+        token_out: Address,
+        amount_in: i128,
+        amount_out_min: i128,
+        distribution: Vec<DexDistribution>,
+        to: Address,
+        deadline: u64,
+    ) -> Vec<Vec<i128>> {
+        to.require_auth();
+        Self::count_swap(&e);
+        let router = Self::router(&e);
+        let shares = Self::shares(&e, amount_in, &distribution, &token_in, &token_out);
+
+        let mut results = vec![&e];
+        let mut amount_out = 0;
+        for (dist, share) in distribution.iter().zip(shares.iter()) {
+            let amounts = router.swap_exact_tokens_for_tokens(&share, &0, &dist.path, &to, &deadline);
+            amount_out += amounts.last().unwrap();
+            results.push_back(amounts);
+        }
+        if amount_out < amount_out_min {
+            panic_with_error!(&e, Error::from_contract_error(SLIPPAGE_EXCEEDED));
+        }
+        results
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_tokens_for_exact_tokens(
+        e: Env,
+        token_in: Address,
+        token_out: Address,
+        amount_out: i128,
+        amount_in_max: i128,
+        distribution: Vec<DexDistribution>,
+        to: Address,
+        deadline: u64,
+    ) -> Vec<Vec<i128>> {
+        to.require_auth();
+        Self::count_swap(&e);
+        let router = Self::router(&e);
+        let shares = Self::shares(&e, amount_out, &distribution, &token_in, &token_out);
+
+        let mut results = vec![&e];
+        let mut amount_in = 0;
+        for (dist, share) in distribution.iter().zip(shares.iter()) {
+            let amounts =
+                router.swap_tokens_for_exact_tokens(&share, &amount_in_max, &dist.path, &to, &deadline);
+            amount_in += amounts.first().unwrap();
+            results.push_back(amounts);
+        }
+        if amount_in > amount_in_max {
+            panic_with_error!(&e, Error::from_contract_error(SLIPPAGE_EXCEEDED));
+        }
+        results
+    }
+}
+
+impl MockAggregator {
+    fn count_swap(e: &Env) {
+        let swaps = Self::swaps(e.clone());
+        e.storage().instance().set(&AggregatorKey::Swaps, &(swaps + 1));
+    }
+
+    fn router(e: &Env) -> MockRouterClient<'_> {
+        let router: Address = e.storage().instance().get(&AggregatorKey::Router).unwrap();
+        MockRouterClient::new(e, &router)
+    }
+
+    /// `amount` split by the distributions' parts, the last taking what
+    /// rounding leaves
+    fn shares(
+        e: &Env,
+        amount: i128,
+        distribution: &Vec<DexDistribution>,
+        token_in: &Address,
+        token_out: &Address,
+    ) -> Vec<i128> {
+        let total_parts: u32 = distribution.iter().map(|dist| dist.parts).sum();
+        let mut shares = vec![e];
+        let mut allocated = 0;
+        for (i, dist) in distribution.iter().enumerate() {
+            assert_eq!(dist.protocol_id, Protocol::Soroswap);
+            assert_eq!(dist.path.first().unwrap(), *token_in);
+            assert_eq!(dist.path.last().unwrap(), *token_out);
+            let share = if i as u32 + 1 == distribution.len() {
+                amount - allocated
+            } else {
+                amount * dist.parts as i128 / total_parts as i128
+            };
+            shares.push_back(share);
+            allocated += share;
+        }
+        shares
+    }
+}
@@ -0,0 +1,117 @@
+use soroban_sdk::{
+    contract, contractimpl, contracttype, panic_with_error, token::TokenClient, vec, Address,
+    BytesN, Env, Error, Map, Vec,
+};
+
+/// Error the mock Aqua pool fails with when a swap returns less than its
+/// minimum, or a quote asks for more than it holds
+pub const OUT_MIN_NOT_SATISFIED: u32 = 1;
+pub const INSUFFICIENT_BALANCE: u32 = 2;
+
+#[contracttype]
+enum AquaPoolKey {
+    Tokens,
+    FeeBps,
+}
+
+/// Aqua constant product pool holding its own liquidity, with its tokens
+/// ordered by address and its fee taken from the input
+#[contract]
+pub struct MockAquaPool;
+
+#[contractimpl]
+impl MockAquaPool {
+    pub fn __constructor(e: Env, token_a: Address, token_b: Address, fee_bps: u128) {
+        let tokens = if token_a < token_b {
+            vec![&e, token_a, token_b]
+        } else {
+            vec![&e, token_b, token_a]
+        };
+        e.storage().instance().set(&AquaPoolKey::Tokens, &tokens);
+        e.storage().instance().set(&AquaPoolKey::FeeBps, &fee_bps);
+    }
+
+    pub fn get_tokens(e: Env) -> Vec<Address> {
+        e.storage().instance().get(&AquaPoolKey::Tokens).unwrap()
+    }
+
+    pub fn get_reserves(e: Env) -> Vec<u128> {
+        let pool = e.current_contract_address();
+        let mut reserves = vec![&e];
+        for token in Self::get_tokens(e.clone()).iter() {
+            reserves.push_back(TokenClient::new(&e, &token).balance(&pool) as u128);
+        }
+        reserves
+    }
+
+    pub fn estimate_swap(e: Env, in_idx: u32, out_idx: u32, in_amount: u128) -> u128 {
+        let reserves = Self::get_reserves(e.clone());
+        let (reserve_in, reserve_out) = (reserves.get(in_idx).unwrap(), reserves.get(out_idx).unwrap());
+        let in_after_fee = in_amount * (10_000 - Self::fee_bps(&e)) / 10_000;
+        reserve_out * in_after_fee / (reserve_in + in_after_fee)
+    }
+
+    pub fn estimate_swap_strict_receive(e: Env, in_idx: u32, out_idx: u32, out_amount: u128) -> u128 {
+        let reserves = Self::get_reserves(e.clone());
+        let (reserve_in, reserve_out) = (reserves.get(in_idx).unwrap(), reserves.get(out_idx).unwrap());
+        if out_amount >= reserve_out {
+            panic_with_error!(&e, Error::from_contract_error(INSUFFICIENT_BALANCE));
+        }
+        let in_after_fee = reserve_in * out_amount / (reserve_out - out_amount) + 1;
+        in_after_fee * 10_000 / (10_000 - Self::fee_bps(&e)) + 1
+    }
+
+    pub fn swap(
+        e: Env,
+        user: Address,
+        in_idx: u32,
+        out_idx: u32,
+        in_amount: u128,
+        out_min: u128,
+    ) -> u128 {
+        user.require_auth();
+        let out_amount = Self::estimate_swap(e.clone(), in_idx, out_idx, in_amount);
+        if out_amount < out_min {
+            panic_with_error!(&e, Error::from_contract_error(OUT_MIN_NOT_SATISFIED));
+        }
+
+        let pool = e.current_contract_address();
+        let tokens = Self::get_tokens(e.clone());
+        TokenClient::new(&e, &tokens.get(in_idx).unwrap()).transfer(&user, &pool, &(in_amount as i128));
+        TokenClient::new(&e, &tokens.get(out_idx).unwrap()).transfer(&pool, &user, &(out_amount as i128));
+        out_amount
+    }
+}
+
+impl MockAquaPool {
+    fn fee_bps(e: &Env) -> u128 {
+        e.storage().instance().get(&AquaPoolKey::FeeBps).unwrap()
+    }
+}
+
+#[contracttype]
+enum AquaRouterKey {
+    Pools(Vec<Address>),
+}
+
+/// Aqua router listing pools by their ordered token pair
+#[contract]
+pub struct MockAquaRouter;
+
+#[contractimpl]
+impl MockAquaRouter {
+    pub fn add_pool(e: Env, pool: Address) {
+        let tokens = MockAquaPoolClient::new(&e, &pool).get_tokens();
+        let mut pools = Self::get_pools(e.clone(), tokens.clone());
+        let index = BytesN::from_array(&e, &[pools.len() as u8; 32]);
+        pools.set(index, pool);
+        e.storage().instance().set(&AquaRouterKey::Pools(tokens), &pools);
+    }
+
+    pub fn get_pools(e: Env, tokens: Vec<Address>) -> Map<BytesN<32>, Address> {
+        e.storage()
+            .instance()
+            .get(&AquaRouterKey::Pools(tokens))
+            .unwrap_or(Map::new(&e))
+    }
+}
@@ -0,0 +1,83 @@
+use soroban_sdk::{
+    contract, contractimpl, contracttype, panic_with_error, token::TokenClient, Address, Env,
+    Error, Vec,
+};
+
+/// Comet `Error::ErrLimitOut`
+pub const ERR_LIMIT_OUT: u32 = 12;
+
+/// Fixed point scalar of Comet's weights and swap fee
+pub const STROOP: i128 = 10_000_000;
+
+#[contracttype]
+enum CometKey {
+    Tokens,
+    Weight(Address),
+    SwapFee,
+}
+
+/// Comet weighted pool holding its own liquidity, priced with floating point
+/// rather than the contract's fixed point math
+#[contract]
+pub struct MockComet;
+
+#[contractimpl]
+impl MockComet {
+    pub fn __constructor(e: Env, swap_fee: i128) {
+        e.storage().instance().set(&CometKey::SwapFee, &swap_fee);
+        e.storage().instance().set(&CometKey::Tokens, &Vec::<Address>::new(&e));
+    }
+
+    /// Adds `token` with a normalized weight (7 decimals)
+    pub fn bind(e: Env, token: Address, weight: i128) {
+        let mut tokens = Self::get_tokens(e.clone());
+        tokens.push_back(token.clone());
+        e.storage().instance().set(&CometKey::Tokens, &tokens);
+        e.storage().instance().set(&CometKey::Weight(token), &weight);
+    }
+
+    pub fn get_tokens(e: Env) -> Vec<Address> {
+        e.storage().instance().get(&CometKey::Tokens).unwrap()
+    }
+
+    pub fn get_balance(e: Env, token: Address) -> i128 {
+        TokenClient::new(&e, &token).balance(&e.current_contract_address())
+    }
+
+    pub fn get_normalized_weight(e: Env, token: Address) -> i128 {
+        e.storage().instance().get(&CometKey::Weight(token)).unwrap()
+    }
+
+    pub fn get_swap_fee(e: Env) -> i128 {
+        e.storage().instance().get(&CometKey::SwapFee).unwrap()
+    }
+
+    pub fn swap_exact_amount_in(
+        e: Env,
+        token_in: Address,
+        token_amount_in: i128,
+        token_out: Address,
+        min_amount_out: i128,
+        _max_price: i128,
+        user: Address,
+    ) -> (i128, i128) {
+        user.require_auth();
+        let balance_in = Self::get_balance(e.clone(), token_in.clone()) as f64;
+        let balance_out = Self::get_balance(e.clone(), token_out.clone()) as f64;
+        let weight_in = Self::get_normalized_weight(e.clone(), token_in.clone()) as f64;
+        let weight_out = Self::get_normalized_weight(e.clone(), token_out.clone()) as f64;
+        let fee = Self::get_swap_fee(e.clone()) as f64 / STROOP as f64;
+
+        let in_after_fee = token_amount_in as f64 * (1.0 - fee);
+        let kept = (balance_in / (balance_in + in_after_fee)).powf(weight_in / weight_out);
+        let amount_out = (balance_out * (1.0 - kept)) as i128;
+        if amount_out < min_amount_out {
+            panic_with_error!(&e, Error::from_contract_error(ERR_LIMIT_OUT));
+        }
+
+        let pool = e.current_contract_address();
+        TokenClient::new(&e, &token_in).transfer(&user, &pool, &token_amount_in);
+        TokenClient::new(&e, &token_out).transfer(&pool, &user, &amount_out);
+        (amount_out, 0)
+    }
+}
@@ -0,0 +1,305 @@
+#![allow(dead_code)]
+
+pub mod aggregator;
+pub mod aqua;
+pub mod comet;
+pub mod oracle;
+pub mod phoenix;
+pub mod pool;
+pub mod router;
+
+use leverage_contract::{Config, LeverageContract, LeverageContractClient};
+use soroban_sdk::{
+    auth::{Context, ContractContext},
+    testutils::{Address as _, Events, Ledger},
+    token::{StellarAssetClient, TokenClient},
+    vec, Address, BytesN, Env, IntoVal, Symbol, TryFromVal, Val, Vec,
+};
+
+use oracle::{MockOracle, MockOracleClient};
+use pool::{FlashLoan, MockPool, MockPoolClient, Request, SCALAR_12};
+use router::{MockPair, MockRouter, MockRouterClient};
+
+// Constants
+pub const SCALAR_7: i128 = 10_000_000;
+pub const ONE_DAY_LEDGERS: u32 = 17280; // assumes 5s a ledger
+
+// Market defaults: USTRY-like collateral at 1.05, oUSDC-like debt at 1.00
+pub const COLLATERAL_PRICE: i128 = 10_500_000;
+pub const DEBT_PRICE: i128 = SCALAR_7;
+pub const COLLATERAL_C_FACTOR: u32 = 9_000_000; // 90%
+pub const DEBT_L_FACTOR: u32 = 9_500_000; // 95%
+pub const MIN_C_FACTOR: i128 = 12_000; // 120%
+pub const TARGET_C_FACTOR: i128 = 13_000; // 130%
+pub const MAX_C_FACTOR: i128 = 14_000; // 140%
+pub const DEFAULT_SLIPPAGE_BPS: i128 = 100; // 1%
+pub const MAX_SLIPPAGE_BPS: i128 = 500; // 5%
+
+// Liquidity seeded into the pool and the swap pair
+pub const POOL_LIQUIDITY: i128 = 1_000_000 * SCALAR_7;
+pub const PAIR_COLLATERAL_RESERVE: i128 = 10_000_000 * SCALAR_7;
+pub const PAIR_DEBT_RESERVE: i128 = 10_500_000 * SCALAR_7;
+
+/// Test environment with a leverage contract wired to mock Blend, Soroswap
+/// and oracle contracts
+pub struct LeverageTestEnv<'a> {
+    pub env: Env,
+    pub owner: Address,
+    pub collateral: Address,
+    pub debt: Address,
+    pub reward: Address,
+    pub oracle: MockOracleClient<'a>,
+    pub pool: MockPoolClient<'a>,
+    pub router: MockRouterClient<'a>,
+    pub pair: Address,
+    pub leverage: LeverageContractClient<'a>,
+}
+
+/// Configuration for the leverage setup
+pub struct LeverageConfig {
+    pub min_c_factor: i128,
+    pub target_c_factor: i128,
+    pub max_c_factor: i128,
+    pub default_slippage_bps: i128,
+    pub max_slippage_bps: i128,
+    pub collateral_c_factor: u32,
+    pub debt_l_factor: u32,
+}
+
+impl Default for LeverageConfig {
+    fn default() -> Self {
+        Self {
+            min_c_factor: MIN_C_FACTOR,
+            target_c_factor: TARGET_C_FACTOR,
+            max_c_factor: MAX_C_FACTOR,
+            default_slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            max_slippage_bps: MAX_SLIPPAGE_BPS,
+            collateral_c_factor: COLLATERAL_C_FACTOR,
+            debt_l_factor: DEBT_L_FACTOR,
+        }
+    }
+}
+
+/// Creates a complete leverage environment with tokens, pool, router and oracle
+pub fn setup_leverage_with_config<'a>(config: LeverageConfig) -> LeverageTestEnv<'a> {
+    let env = Env::default();
+    env.cost_estimate().budget().reset_unlimited();
+    env.mock_all_auths();
+
+    // Set TTL parameters
+    env.ledger().set_min_temp_entry_ttl(ONE_DAY_LEDGERS);
+    env.ledger().set_min_persistent_entry_ttl(ONE_DAY_LEDGERS * 30);
+    env.ledger().set_timestamp(1_700_000_000);
+
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+
+    // Deploy tokens
+    let collateral = env.register_stellar_asset_contract_v2(admin.clone()).address();
+    let debt = env.register_stellar_asset_contract_v2(admin.clone()).address();
+    let reward = env.register_stellar_asset_contract_v2(admin.clone()).address();
+
+    // Deploy oracle
+    let oracle = MockOracleClient::new(&env, &env.register(MockOracle, (7u32,)));
+    oracle.set_price(&collateral, &COLLATERAL_PRICE);
+    oracle.set_price(&debt, &DEBT_PRICE);
+
+    // Deploy pool with the debt reserve listed first so reserve indices differ
+    // from the asset order used by the contract
+    let pool_address = env.register(MockPool, (oracle.address.clone(), reward.clone()));
+    let pool = MockPoolClient::new(&env, &pool_address);
+    pool.add_reserve(&debt, &0, &config.debt_l_factor);
+    pool.add_reserve(&collateral, &config.collateral_c_factor, &(SCALAR_7 as u32));
+    StellarAssetClient::new(&env, &debt).mint(&pool.address, &POOL_LIQUIDITY);
+
+    // Deploy router and a collateral/debt pair
+    let router = MockRouterClient::new(&env, &env.register(MockRouter, ()));
+    let pair = env.register(MockPair, (collateral.clone(), debt.clone()));
+    router.add_pair(&collateral, &debt, &pair);
+    StellarAssetClient::new(&env, &collateral).mint(&pair, &PAIR_COLLATERAL_RESERVE);
+    StellarAssetClient::new(&env, &debt).mint(&pair, &PAIR_DEBT_RESERVE);
+
+    // Deploy leverage contract
+    let leverage_address = env.register(
+        LeverageContract,
+        (Config {
+            owner: owner.clone(),
+            blend_pool: pool.address.clone(),
+            collateral_asset: collateral.clone(),
+            debt_asset: debt.clone(),
+            reward_token: reward.clone(),
+            swap_router: router.address.clone(),
+            oracle: oracle.address.clone(),
+            min_c_factor: config.min_c_factor,
+            target_c_factor: config.target_c_factor,
+            max_c_factor: config.max_c_factor,
+            default_slippage_bps: config.default_slippage_bps,
+            max_slippage_bps: config.max_slippage_bps,
+        },),
+    );
+    let leverage = LeverageContractClient::new(&env, &leverage_address);
+
+    LeverageTestEnv {
+        env,
+        owner,
+        collateral,
+        debt,
+        reward,
+        oracle,
+        pool,
+        router,
+        pair,
+        leverage,
+    }
+}
+
+/// Creates a leverage environment with default configuration
+pub fn setup_leverage<'a>() -> LeverageTestEnv<'a> {
+    setup_leverage_with_config(LeverageConfig::default())
+}
+
+/// Helper functions for LeverageTestEnv
+impl<'a> LeverageTestEnv<'a> {
+    /// Mint tokens of `asset` to any address
+    pub fn mint(&self, asset: &Address, to: &Address, amount: i128) {
+        StellarAssetClient::new(&self.env, asset).mint(to, &amount);
+    }
+
+    /// Get the `asset` balance of an address
+    pub fn balance(&self, asset: &Address, address: &Address) -> i128 {
+        TokenClient::new(&self.env, asset).balance(address)
+    }
+
+    /// Allows `lender` and has the owner start a flash loan from it
+    pub fn begin_flash_loan(&self, lender: &Address, asset: &Address, amount: i128) {
+        self.leverage.set_flash_lender(lender, &true);
+        self.leverage.begin_flash_loan(lender, asset, &amount);
+    }
+
+    /// Acts as an allowed external flash lender: the owner starts the loan,
+    /// then the lender sends `amount` of `asset` to the contract and calls
+    /// `exec_op` the way a lender would
+    pub fn flash_loan(&self, lender: &Address, asset: &Address, amount: i128, fee: i128) {
+        self.begin_flash_loan(lender, asset, amount);
+        self.mint(asset, &self.leverage.address, amount);
+        self.leverage.exec_op(lender, asset, &amount, &fee);
+    }
+
+    /// Runs the contract's `__check_auth` for a Blend flash loan of `amount`
+    /// debt with `requests`, the way the host does before `flash_loan`
+    pub fn check_blend_flash_loan(
+        &self,
+        amount: i128,
+        requests: &Vec<Request>,
+    ) -> Result<(), Result<leverage_contract::LeverageError, soroban_sdk::InvokeError>> {
+        let flash_loan = FlashLoan {
+            amount,
+            asset: self.debt.clone(),
+            contract: self.leverage.address.clone(),
+        };
+        let context = Context::Contract(ContractContext {
+            contract: self.pool.address.clone(),
+            fn_name: Symbol::new(&self.env, "flash_loan"),
+            args: (self.leverage.address.clone(), flash_loan, requests.clone()).into_val(&self.env),
+        });
+        self.env.try_invoke_contract_check_auth(
+            &self.leverage.address,
+            &BytesN::from_array(&self.env, &[0; 32]),
+            ().into_val(&self.env),
+            &vec![&self.env, context],
+        )
+    }
+
+    /// Takes a Blend flash loan of `amount` debt on the contract's position
+    /// that supplies `supply` collateral once `exec_op` returns
+    pub fn blend_flash_loan(&self, amount: i128, supply: i128) {
+        let requests = vec![
+            &self.env,
+            Request {
+                address: self.collateral.clone(),
+                amount: supply,
+                request_type: 2,
+            },
+        ];
+        self.check_blend_flash_loan(amount, &requests).unwrap();
+        // The owner also signs `exec_op`, below the pool's `flash_loan`
+        self.env.mock_all_auths_allowing_non_root_auth();
+        self.pool.flash_loan(
+            &self.leverage.address,
+            &FlashLoan {
+                amount,
+                asset: self.debt.clone(),
+                contract: self.leverage.address.clone(),
+            },
+            &requests,
+        );
+    }
+
+    /// Collateral and debt of the contract's Blend position, in underlying
+    pub fn position(&self) -> (i128, i128) {
+        let positions = self.pool.get_positions(&self.leverage.address);
+        let collateral_reserve = self.pool.get_reserve(&self.collateral);
+        let debt_reserve = self.pool.get_reserve(&self.debt);
+        let b_tokens = positions
+            .collateral
+            .get(collateral_reserve.config.index)
+            .unwrap_or(0);
+        let d_tokens = positions
+            .liabilities
+            .get(debt_reserve.config.index)
+            .unwrap_or(0);
+        (
+            b_tokens * collateral_reserve.data.b_rate / SCALAR_12,
+            (d_tokens * debt_reserve.data.d_rate + SCALAR_12 - 1) / SCALAR_12,
+        )
+    }
+
+    /// Oracle price of `asset`
+    pub fn price(&self, asset: &Address) -> i128 {
+        self.oracle
+            .lastprice(&oracle::Asset::Stellar(asset.clone()))
+            .unwrap()
+            .price
+    }
+
+    /// Data of the last event the leverage contract published under `topic`
+    pub fn last_event<T: TryFromVal<Env, Val>>(&self, topic: Symbol) -> T {
+        let (_, _, data) = self
+            .env
+            .events()
+            .all()
+            .iter()
+            .filter(|(contract, topics, _)| {
+                *contract == self.leverage.address
+                    && topics
+                        .first()
+                        .is_some_and(|t| Symbol::try_from_val(&self.env, &t).ok() == Some(topic.clone()))
+            })
+            .last()
+            .expect("no event published under topic");
+        T::try_from_val(&self.env, &data).unwrap()
+    }
+
+    /// Collateral value / debt value of the contract's position, in bps
+    pub fn c_factor(&self) -> i128 {
+        let (collateral, debt) = self.position();
+        let collateral_price = self.price(&self.collateral);
+        let debt_price = self.price(&self.debt);
+        collateral * collateral_price * 10_000 / (debt * debt_price)
+    }
+}
+
+/// Asserts two values are approximately equal (within `bps` basis points)
+pub fn assert_approx_eq_bps(actual: i128, expected: i128, bps: i128, msg: &str) {
+    let tolerance = expected.abs() * bps / 10_000;
+    let diff = (actual - expected).abs();
+    assert!(
+        diff <= tolerance,
+        "{}: expected {} ± {}, got {} (diff: {})",
+        msg,
+        expected,
+        tolerance,
+        actual,
+        diff
+    );
+}
@@ -0,0 +1,52 @@
+use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Symbol};
+
+/// SEP-40 asset identifier
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Asset {
+    Stellar(Address),
+    Other(Symbol),
+}
+
+/// SEP-40 price record
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PriceData {
+    pub price: i128,
+    pub timestamp: u64,
+}
+
+#[contracttype]
+enum OracleKey {
+    Decimals,
+    Price(Asset),
+}
+
+/// Minimal SEP-40 price feed with prices set directly by the test
+#[contract]
+pub struct MockOracle;
+
+#[contractimpl]
+impl MockOracle {
+    pub fn __constructor(e: Env, decimals: u32) {
+        e.storage().instance().set(&OracleKey::Decimals, &decimals);
+    }
+
+    pub fn set_price(e: Env, asset: Address, price: i128) {
+        let data = PriceData {
+            price,
+            timestamp: e.ledger().timestamp(),
+        };
+        e.storage()
+            .instance()
+            .set(&OracleKey::Price(Asset::Stellar(asset)), &data);
+    }
+
+    pub fn decimals(e: Env) -> u32 {
+        e.storage().instance().get(&OracleKey::Decimals).unwrap()
+    }
+
+    pub fn lastprice(e: Env, asset: Asset) -> Option<PriceData> {
+        e.storage().instance().get(&OracleKey::Price(asset))
+    }
+}
@@ -0,0 +1,149 @@
+use soroban_sdk::{
+    contract, contractimpl, contracttype, panic_with_error, token::TokenClient, Address, Env,
+    Error,
+};
+
+/// Phoenix `ContractError::SlippageToleranceExceeded`
+pub const SLIPPAGE_TOLERANCE_EXCEEDED: u32 = 107;
+/// Phoenix factory `ContractError::LiquidityPoolNotFound`
+pub const LIQUIDITY_POOL_NOT_FOUND: u32 = 6;
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulateSwapResponse {
+    pub ask_amount: i128,
+    pub commission_amount: i128,
+    pub spread_amount: i128,
+    pub total_return: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulateReverseSwapResponse {
+    pub offer_amount: i128,
+    pub commission_amount: i128,
+    pub spread_amount: i128,
+}
+
+#[contracttype]
+enum PoolKey {
+    TokenA,
+    TokenB,
+    FeeBps,
+}
+
+/// Phoenix constant product pool holding its own liquidity, taking its
+/// commission from the return
+#[contract]
+pub struct MockPhoenixPool;
+
+#[contractimpl]
+impl MockPhoenixPool {
+    pub fn __constructor(e: Env, token_a: Address, token_b: Address, fee_bps: i128) {
+        e.storage().instance().set(&PoolKey::TokenA, &token_a);
+        e.storage().instance().set(&PoolKey::TokenB, &token_b);
+        e.storage().instance().set(&PoolKey::FeeBps, &fee_bps);
+    }
+
+    pub fn simulate_swap(e: Env, offer_asset: Address, offer_amount: i128) -> SimulateSwapResponse {
+        let (offer_pool, ask_pool) = Self::pools(&e, &offer_asset);
+        let fee_bps: i128 = e.storage().instance().get(&PoolKey::FeeBps).unwrap();
+        let return_amount = ask_pool * offer_amount / (offer_pool + offer_amount);
+        let commission_amount = return_amount * fee_bps / 10_000;
+        SimulateSwapResponse {
+            ask_amount: return_amount - commission_amount,
+            commission_amount,
+            spread_amount: offer_amount * ask_pool / offer_pool - return_amount,
+            total_return: return_amount,
+        }
+    }
+
+    pub fn simulate_reverse_swap(
+        e: Env,
+        ask_asset: Address,
+        ask_amount: i128,
+    ) -> SimulateReverseSwapResponse {
+        let (ask_pool, offer_pool) = Self::pools(&e, &ask_asset);
+        let fee_bps: i128 = e.storage().instance().get(&PoolKey::FeeBps).unwrap();
+        let return_amount = ask_amount * 10_000 / (10_000 - fee_bps);
+        let offer_amount = offer_pool * ask_pool / (ask_pool - return_amount) - offer_pool;
+        SimulateReverseSwapResponse {
+            offer_amount,
+            commission_amount: return_amount - ask_amount,
+            spread_amount: offer_amount * ask_pool / offer_pool - return_amount,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap(
+        e: Env,
+        sender: Address,
+        offer_asset: Address,
+        offer_amount: i128,
+        ask_asset_min_amount: Option<i128>,
+        _max_spread_bps: Option<i64>,
+        _deadline: Option<u64>,
+        _max_allowed_fee_bps: Option<i64>,
+    ) -> i128 {
+        sender.require_auth();
+        let ask_amount = Self::simulate_swap(e.clone(), offer_asset.clone(), offer_amount).ask_amount;
+        if ask_amount < ask_asset_min_amount.unwrap_or(0) {
+            panic_with_error!(&e, Error::from_contract_error(SLIPPAGE_TOLERANCE_EXCEEDED));
+        }
+
+        let pool = e.current_contract_address();
+        TokenClient::new(&e, &offer_asset).transfer(&sender, &pool, &offer_amount);
+        TokenClient::new(&e, &Self::other(&e, &offer_asset)).transfer(&pool, &sender, &ask_amount);
+        ask_amount
+    }
+}
+
+impl MockPhoenixPool {
+    /// Balances of `asset` and of the other token
+    fn pools(e: &Env, asset: &Address) -> (i128, i128) {
+        let pool = e.current_contract_address();
+        (
+            TokenClient::new(e, asset).balance(&pool),
+            TokenClient::new(e, &Self::other(e, asset)).balance(&pool),
+        )
+    }
+
+    fn other(e: &Env, asset: &Address) -> Address {
+        let token_a: Address = e.storage().instance().get(&PoolKey::TokenA).unwrap();
+        if *asset == token_a {
+            e.storage().instance().get(&PoolKey::TokenB).unwrap()
+        } else {
+            token_a
+        }
+    }
+}
+
+#[contracttype]
+enum FactoryKey {
+    Pool(Address, Address),
+}
+
+/// Phoenix factory listing pools by token pair
+#[contract]
+pub struct MockPhoenixFactory;
+
+#[contractimpl]
+impl MockPhoenixFactory {
+    pub fn add_pool(e: Env, token_a: Address, token_b: Address, pool: Address) {
+        e.storage()
+            .instance()
+            .set(&FactoryKey::Pool(token_a.clone(), token_b.clone()), &pool);
+        e.storage()
+            .instance()
+            .set(&FactoryKey::Pool(token_b, token_a), &pool);
+    }
+
+    pub fn query_for_pool_by_token_pair(e: Env, token_a: Address, token_b: Address) -> Address {
+        e.storage()
+            .instance()
+            .get(&FactoryKey::Pool(token_a, token_b))
+            .unwrap_or_else(|| {
+                panic_with_error!(&e, Error::from_contract_error(LIQUIDITY_POOL_NOT_FOUND))
+            })
+    }
+}
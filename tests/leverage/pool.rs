@@ -0,0 +1,340 @@
+use soroban_sdk::{
+    contract, contractclient, contractimpl, contracttype, panic_with_error, token::TokenClient,
+    Address, Env, Error, Map, Vec,
+};
+
+use super::oracle::{Asset, MockOracleClient};
+
+#[allow(clippy::too_many_arguments)]
+pub mod blend {
+    soroban_sdk::contractimport!(file = "./pool.wasm");
+}
+
+pub use blend::{FlashLoan, PoolConfig, Positions, Request, Reserve, ReserveConfig, ReserveData};
+
+pub const SCALAR_7: i128 = 10_000_000;
+pub const SCALAR_12: i128 = 1_000_000_000_000;
+
+/// Blend `PoolError::InvalidHf`
+pub const INVALID_HF: u32 = 1205;
+/// Blend `PoolError::InvalidPoolStatus`
+pub const INVALID_POOL_STATUS: u32 = 1206;
+
+/// Blend pool statuses from which borrowing, and then supplying, is blocked
+pub const STATUS_ON_ICE: u32 = 2;
+pub const STATUS_FROZEN: u32 = 4;
+
+#[contracttype]
+enum PoolKey {
+    Oracle,
+    ReserveList,
+    Reserve(Address),
+    Positions(Address),
+    RewardToken,
+    Emissions,
+    Status,
+    ClaimIds,
+    Submits,
+}
+
+/// Receiver Blend calls back into during `flash_loan`
+#[contractclient(name = "FlashLoanReceiverClient")]
+pub trait FlashLoanReceiver {
+    fn exec_op(e: Env, caller: Address, token: Address, amount: i128, fee: i128);
+}
+
+/// Stand-in for a Blend v2 pool
+///
+/// Mirrors Blend's bToken/dToken accounting, rounding, request types and the
+/// end-of-submit health check, priced off a SEP-40 oracle.
+#[contract]
+pub struct MockPool;
+
+#[contractimpl]
+impl MockPool {
+    pub fn __constructor(e: Env, oracle: Address, reward_token: Address) {
+        e.storage().instance().set(&PoolKey::Oracle, &oracle);
+        e.storage().instance().set(&PoolKey::RewardToken, &reward_token);
+        e.storage()
+            .instance()
+            .set(&PoolKey::ReserveList, &Vec::<Address>::new(&e));
+    }
+
+    /// Lists a reserve at the next index with the given factors (7 decimals)
+    pub fn add_reserve(e: Env, asset: Address, c_factor: u32, l_factor: u32) {
+        let mut list: Vec<Address> = e.storage().instance().get(&PoolKey::ReserveList).unwrap();
+        let reserve = Reserve {
+            asset: asset.clone(),
+            config: ReserveConfig {
+                c_factor,
+                decimals: 7,
+                enabled: true,
+                index: list.len(),
+                l_factor,
+                max_util: 9_500_000,
+                r_base: 0,
+                r_one: 0,
+                r_three: 0,
+                r_two: 0,
+                reactivity: 0,
+                supply_cap: i128::MAX,
+                util: 8_000_000,
+            },
+            data: ReserveData {
+                b_rate: SCALAR_12,
+                b_supply: 0,
+                backstop_credit: 0,
+                d_rate: SCALAR_12,
+                d_supply: 0,
+                ir_mod: SCALAR_7,
+                last_time: e.ledger().timestamp(),
+            },
+            scalar: SCALAR_7,
+        };
+        list.push_back(asset.clone());
+        e.storage().instance().set(&PoolKey::ReserveList, &list);
+        e.storage().instance().set(&PoolKey::Reserve(asset), &reserve);
+    }
+
+    /// Overrides the b_rate and d_rate (12 decimals) of a reserve
+    pub fn set_rates(e: Env, asset: Address, b_rate: i128, d_rate: i128) {
+        let mut reserve = Self::get_reserve(e.clone(), asset.clone());
+        reserve.data.b_rate = b_rate;
+        reserve.data.d_rate = d_rate;
+        e.storage().instance().set(&PoolKey::Reserve(asset), &reserve);
+    }
+
+    /// Sets the pool status, 0 (active) by default
+    pub fn set_status(e: Env, status: u32) {
+        e.storage().instance().set(&PoolKey::Status, &status);
+    }
+
+    /// Sets the amount of reward token paid out by the next `claim`
+    pub fn set_emissions(e: Env, amount: i128) {
+        e.storage().instance().set(&PoolKey::Emissions, &amount);
+    }
+
+    /// Reserve token ids passed to the last `claim`
+    pub fn last_claim_ids(e: Env) -> Vec<u32> {
+        e.storage().instance().get(&PoolKey::ClaimIds).unwrap()
+    }
+
+    /// Number of `submit` calls so far
+    pub fn submits(e: Env) -> u32 {
+        e.storage().instance().get(&PoolKey::Submits).unwrap_or(0)
+    }
+
+    pub fn get_config(e: Env) -> PoolConfig {
+        PoolConfig {
+            bstop_rate: 0,
+            max_positions: 4,
+            min_collateral: 0,
+            oracle: e.storage().instance().get(&PoolKey::Oracle).unwrap(),
+            status: e.storage().instance().get(&PoolKey::Status).unwrap_or(0),
+        }
+    }
+
+    pub fn get_reserve_list(e: Env) -> Vec<Address> {
+        e.storage().instance().get(&PoolKey::ReserveList).unwrap()
+    }
+
+    pub fn get_reserve(e: Env, asset: Address) -> Reserve {
+        e.storage()
+            .instance()
+            .get(&PoolKey::Reserve(asset))
+            .unwrap_or_else(|| panic_with_error!(&e, Error::from_contract_error(1209)))
+    }
+
+    pub fn get_positions(e: Env, address: Address) -> Positions {
+        e.storage()
+            .persistent()
+            .get(&PoolKey::Positions(address))
+            .unwrap_or(Positions {
+                collateral: Map::new(&e),
+                liabilities: Map::new(&e),
+                supply: Map::new(&e),
+            })
+    }
+
+    pub fn submit(
+        e: Env,
+        from: Address,
+        spender: Address,
+        to: Address,
+        requests: Vec<Request>,
+    ) -> Positions {
+        from.require_auth();
+        let submits: u32 = e.storage().instance().get(&PoolKey::Submits).unwrap_or(0);
+        e.storage().instance().set(&PoolKey::Submits, &(submits + 1));
+        let positions = Self::get_positions(e.clone(), from.clone());
+        Self::apply_requests(&e, from, spender, to, positions, requests)
+    }
+
+    /// Borrows `flash_loan.amount` for `from` into `flash_loan.contract`,
+    /// calls its `exec_op`, then applies `requests` for `from` with a single
+    /// health check at the end
+    pub fn flash_loan(
+        e: Env,
+        from: Address,
+        flash_loan: FlashLoan,
+        requests: Vec<Request>,
+    ) -> Positions {
+        from.require_auth();
+        let mut positions = Self::get_positions(e.clone(), from.clone());
+        let reserve = Self::get_reserve(e.clone(), flash_loan.asset.clone());
+        let index = reserve.config.index;
+        let d_tokens = ceil_div(flash_loan.amount * SCALAR_12, reserve.data.d_rate);
+        let balance = positions.liabilities.get(index).unwrap_or(0);
+        positions.liabilities.set(index, balance + d_tokens);
+
+        TokenClient::new(&e, &flash_loan.asset).transfer(
+            &e.current_contract_address(),
+            &flash_loan.contract,
+            &flash_loan.amount,
+        );
+        FlashLoanReceiverClient::new(&e, &flash_loan.contract).exec_op(
+            &from,
+            &flash_loan.asset,
+            &flash_loan.amount,
+            &0,
+        );
+
+        Self::apply_requests(&e, from.clone(), from.clone(), from, positions, requests)
+    }
+
+    pub fn claim(e: Env, from: Address, reserve_token_ids: Vec<u32>, to: Address) -> i128 {
+        from.require_auth();
+        e.storage()
+            .instance()
+            .set(&PoolKey::ClaimIds, &reserve_token_ids);
+        let amount: i128 = e.storage().instance().get(&PoolKey::Emissions).unwrap_or(0);
+        if amount > 0 {
+            let reward: Address = e.storage().instance().get(&PoolKey::RewardToken).unwrap();
+            TokenClient::new(&e, &reward).transfer(&e.current_contract_address(), &to, &amount);
+            e.storage().instance().set(&PoolKey::Emissions, &0i128);
+        }
+        amount
+    }
+}
+
+impl MockPool {
+    fn apply_requests(
+        e: &Env,
+        from: Address,
+        spender: Address,
+        to: Address,
+        mut positions: Positions,
+        requests: Vec<Request>,
+    ) -> Positions {
+        let status = Self::get_config(e.clone()).status;
+        let mut spender_transfers: Vec<(Address, i128)> = Vec::new(e);
+        let mut pool_transfers: Vec<(Address, i128)> = Vec::new(e);
+
+        for request in requests.iter() {
+            let reserve = Self::get_reserve(e.clone(), request.address.clone());
+            let index = reserve.config.index;
+            if (request.request_type == 4 && status >= STATUS_ON_ICE)
+                || (request.request_type == 2 && status >= STATUS_FROZEN)
+            {
+                panic_with_error!(e, Error::from_contract_error(INVALID_POOL_STATUS));
+            }
+            match request.request_type {
+                2 => {
+                    let b_tokens = request.amount * SCALAR_12 / reserve.data.b_rate;
+                    let balance = positions.collateral.get(index).unwrap_or(0);
+                    positions.collateral.set(index, balance + b_tokens);
+                    spender_transfers.push_back((request.address.clone(), request.amount));
+                }
+                3 => {
+                    let balance = positions.collateral.get(index).unwrap_or(0);
+                    let mut b_tokens = ceil_div(request.amount * SCALAR_12, reserve.data.b_rate);
+                    let mut amount = request.amount;
+                    if b_tokens > balance {
+                        b_tokens = balance;
+                        amount = balance * reserve.data.b_rate / SCALAR_12;
+                    }
+                    set_or_remove(&mut positions.collateral, index, balance - b_tokens);
+                    pool_transfers.push_back((request.address.clone(), amount));
+                }
+                4 => {
+                    let d_tokens = ceil_div(request.amount * SCALAR_12, reserve.data.d_rate);
+                    let balance = positions.liabilities.get(index).unwrap_or(0);
+                    positions.liabilities.set(index, balance + d_tokens);
+                    pool_transfers.push_back((request.address.clone(), request.amount));
+                }
+                5 => {
+                    let balance = positions.liabilities.get(index).unwrap_or(0);
+                    let d_tokens = request.amount * SCALAR_12 / reserve.data.d_rate;
+                    spender_transfers.push_back((request.address.clone(), request.amount));
+                    if d_tokens > balance {
+                        let owed = ceil_div(balance * reserve.data.d_rate, SCALAR_12);
+                        pool_transfers.push_back((request.address.clone(), request.amount - owed));
+                        set_or_remove(&mut positions.liabilities, index, 0);
+                    } else {
+                        set_or_remove(&mut positions.liabilities, index, balance - d_tokens);
+                    }
+                }
+                _ => panic_with_error!(e, Error::from_contract_error(1200)),
+            }
+        }
+
+        Self::require_healthy(e, &positions);
+        e.storage()
+            .persistent()
+            .set(&PoolKey::Positions(from), &positions);
+
+        let pool = e.current_contract_address();
+        for (asset, amount) in spender_transfers.iter() {
+            TokenClient::new(e, &asset).transfer(&spender, &pool, &amount);
+        }
+        for (asset, amount) in pool_transfers.iter() {
+            if amount > 0 {
+                TokenClient::new(e, &asset).transfer(&pool, &to, &amount);
+            }
+        }
+        positions
+    }
+
+    fn require_healthy(e: &Env, positions: &Positions) {
+        if positions.liabilities.is_empty() {
+            return;
+        }
+        let oracle: Address = e.storage().instance().get(&PoolKey::Oracle).unwrap();
+        let oracle = MockOracleClient::new(e, &oracle);
+        let list = Self::get_reserve_list(e.clone());
+
+        let mut effective_collateral = 0;
+        let mut effective_liabilities = 0;
+        for asset in list.iter() {
+            let reserve = Self::get_reserve(e.clone(), asset.clone());
+            let index = reserve.config.index;
+            let b_tokens = positions.collateral.get(index).unwrap_or(0);
+            let d_tokens = positions.liabilities.get(index).unwrap_or(0);
+            if b_tokens == 0 && d_tokens == 0 {
+                continue;
+            }
+            let price = oracle.lastprice(&Asset::Stellar(asset)).unwrap().price;
+            let collateral = b_tokens * reserve.data.b_rate / SCALAR_12;
+            let liabilities = ceil_div(d_tokens * reserve.data.d_rate, SCALAR_12);
+            effective_collateral +=
+                collateral * price / SCALAR_7 * reserve.config.c_factor as i128 / SCALAR_7;
+            effective_liabilities +=
+                liabilities * price / SCALAR_7 * SCALAR_7 / reserve.config.l_factor as i128;
+        }
+        if effective_collateral <= effective_liabilities {
+            panic_with_error!(e, Error::from_contract_error(INVALID_HF));
+        }
+    }
+}
+
+fn ceil_div(numerator: i128, denominator: i128) -> i128 {
+    (numerator + denominator - 1) / denominator
+}
+
+fn set_or_remove(map: &mut Map<u32, i128>, index: u32, value: i128) {
+    if value > 0 {
+        map.set(index, value);
+    } else {
+        map.remove(index);
+    }
+}
@@ -0,0 +1,195 @@
+use soroban_sdk::{
+    contract, contractimpl, contracttype, panic_with_error, token::TokenClient, vec, Address,
+    Env, Error, Vec,
+};
+
+/// Soroswap `SoroswapLibraryError::InsufficientLiquidity`
+pub const INSUFFICIENT_LIQUIDITY: u32 = 302;
+/// Soroswap `SoroswapRouterError::InsufficientOutputAmount`
+pub const INSUFFICIENT_OUTPUT_AMOUNT: u32 = 407;
+/// Soroswap `SoroswapRouterError::ExcessiveInputAmount`
+pub const EXCESSIVE_INPUT_AMOUNT: u32 = 408;
+/// Soroswap `SoroswapRouterError::PairDoesNotExist`
+pub const PAIR_DOES_NOT_EXIST: u32 = 409;
+
+#[contracttype]
+enum PairKey {
+    Token0,
+    Token1,
+}
+
+/// Constant product pair holding its own liquidity
+#[contract]
+pub struct MockPair;
+
+#[contractimpl]
+impl MockPair {
+    pub fn __constructor(e: Env, token_0: Address, token_1: Address) {
+        e.storage().instance().set(&PairKey::Token0, &token_0);
+        e.storage().instance().set(&PairKey::Token1, &token_1);
+    }
+
+    pub fn token_0(e: Env) -> Address {
+        e.storage().instance().get(&PairKey::Token0).unwrap()
+    }
+
+    pub fn token_1(e: Env) -> Address {
+        e.storage().instance().get(&PairKey::Token1).unwrap()
+    }
+
+    /// Reserves are the pair's token balances
+    pub fn get_reserves(e: Env) -> (i128, i128) {
+        let pair = e.current_contract_address();
+        (
+            TokenClient::new(&e, &Self::token_0(e.clone())).balance(&pair),
+            TokenClient::new(&e, &Self::token_1(e.clone())).balance(&pair),
+        )
+    }
+
+    pub fn swap(e: Env, amount_0_out: i128, amount_1_out: i128, to: Address) {
+        let pair = e.current_contract_address();
+        if amount_0_out > 0 {
+            TokenClient::new(&e, &Self::token_0(e.clone())).transfer(&pair, &to, &amount_0_out);
+        }
+        if amount_1_out > 0 {
+            TokenClient::new(&e, &Self::token_1(e.clone())).transfer(&pair, &to, &amount_1_out);
+        }
+    }
+}
+
+#[contracttype]
+enum RouterKey {
+    Pair(Address, Address),
+}
+
+/// Soroswap router over `MockPair`s, using Soroswap's 0.3% fee math
+#[contract]
+pub struct MockRouter;
+
+#[contractimpl]
+impl MockRouter {
+    pub fn add_pair(e: Env, token_a: Address, token_b: Address, pair: Address) {
+        e.storage()
+            .instance()
+            .set(&RouterKey::Pair(token_a.clone(), token_b.clone()), &pair);
+        e.storage()
+            .instance()
+            .set(&RouterKey::Pair(token_b, token_a), &pair);
+    }
+
+    pub fn router_pair_for(e: Env, token_a: Address, token_b: Address) -> Address {
+        e.storage()
+            .instance()
+            .get(&RouterKey::Pair(token_a, token_b))
+            .unwrap_or_else(|| panic_with_error!(&e, Error::from_contract_error(PAIR_DOES_NOT_EXIST)))
+    }
+
+    pub fn router_get_amounts_out(e: Env, amount_in: i128, path: Vec<Address>) -> Vec<i128> {
+        let mut amounts = vec![&e, amount_in];
+        for i in 0..path.len() - 1 {
+            let (reserve_in, reserve_out) =
+                Self::reserves(&e, path.get(i).unwrap(), path.get(i + 1).unwrap());
+            let amount_out = get_amount_out(&e, amounts.get(i).unwrap(), reserve_in, reserve_out);
+            amounts.push_back(amount_out);
+        }
+        amounts
+    }
+
+    pub fn router_get_amounts_in(e: Env, amount_out: i128, path: Vec<Address>) -> Vec<i128> {
+        let mut amounts = vec![&e, amount_out];
+        for i in (1..path.len()).rev() {
+            let (reserve_in, reserve_out) =
+                Self::reserves(&e, path.get(i - 1).unwrap(), path.get(i).unwrap());
+            let amount_in = get_amount_in(&e, amounts.get(0).unwrap(), reserve_in, reserve_out);
+            amounts.push_front(amount_in);
+        }
+        amounts
+    }
+
+    pub fn swap_exact_tokens_for_tokens(
+        e: Env,
+        amount_in: i128,
+        amount_out_min: i128,
+        path: Vec<Address>,
+        to: Address,
+        _deadline: u64,
+    ) -> Vec<i128> {
+        to.require_auth();
+        let amounts = Self::router_get_amounts_out(e.clone(), amount_in, path.clone());
+        if amounts.last().unwrap() < amount_out_min {
+            panic_with_error!(&e, Error::from_contract_error(INSUFFICIENT_OUTPUT_AMOUNT));
+        }
+        Self::execute(&e, &amounts, &path, &to);
+        amounts
+    }
+
+    pub fn swap_tokens_for_exact_tokens(
+        e: Env,
+        amount_out: i128,
+        amount_in_max: i128,
+        path: Vec<Address>,
+        to: Address,
+        _deadline: u64,
+    ) -> Vec<i128> {
+        to.require_auth();
+        let amounts = Self::router_get_amounts_in(e.clone(), amount_out, path.clone());
+        if amounts.first().unwrap() > amount_in_max {
+            panic_with_error!(&e, Error::from_contract_error(EXCESSIVE_INPUT_AMOUNT));
+        }
+        Self::execute(&e, &amounts, &path, &to);
+        amounts
+    }
+}
+
+impl MockRouter {
+    fn reserves(e: &Env, token_in: Address, token_out: Address) -> (i128, i128) {
+        let pair = Self::router_pair_for(e.clone(), token_in.clone(), token_out);
+        let pair = MockPairClient::new(e, &pair);
+        let (reserve_0, reserve_1) = pair.get_reserves();
+        if pair.token_0() == token_in {
+            (reserve_0, reserve_1)
+        } else {
+            (reserve_1, reserve_0)
+        }
+    }
+
+    fn execute(e: &Env, amounts: &Vec<i128>, path: &Vec<Address>, to: &Address) {
+        let first_pair =
+            Self::router_pair_for(e.clone(), path.get(0).unwrap(), path.get(1).unwrap());
+        TokenClient::new(e, &path.get(0).unwrap()).transfer(to, &first_pair, &amounts.get(0).unwrap());
+        for i in 0..path.len() - 1 {
+            let token_in = path.get(i).unwrap();
+            let token_out = path.get(i + 1).unwrap();
+            let pair = MockPairClient::new(
+                e,
+                &Self::router_pair_for(e.clone(), token_in.clone(), token_out.clone()),
+            );
+            let recipient = if i + 2 < path.len() {
+                Self::router_pair_for(e.clone(), token_out.clone(), path.get(i + 2).unwrap())
+            } else {
+                to.clone()
+            };
+            let amount_out = amounts.get(i + 1).unwrap();
+            if pair.token_0() == token_out {
+                pair.swap(&amount_out, &0, &recipient);
+            } else {
+                pair.swap(&0, &amount_out, &recipient);
+            }
+        }
+    }
+}
+
+fn get_amount_out(e: &Env, amount_in: i128, reserve_in: i128, reserve_out: i128) -> i128 {
+    if reserve_in <= 0 || reserve_out <= 0 {
+        panic_with_error!(e, Error::from_contract_error(INSUFFICIENT_LIQUIDITY));
+    }
+    let amount_in_with_fee = amount_in * 997;
+    amount_in_with_fee * reserve_out / (reserve_in * 1000 + amount_in_with_fee)
+}
+
+fn get_amount_in(e: &Env, amount_out: i128, reserve_in: i128, reserve_out: i128) -> i128 {
+    if reserve_in <= 0 || reserve_out <= amount_out {
+        panic_with_error!(e, Error::from_contract_error(INSUFFICIENT_LIQUIDITY));
+    }
+    reserve_in * amount_out * 1000 / ((reserve_out - amount_out) * 997) + 1
+}
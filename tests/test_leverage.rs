@@ -0,0 +1,2007 @@
+mod leverage;
+use leverage::*;
+use leverage_contract::{
+    ClaimEvent, CompoundEvent, Config, DeleverageEvent, LeverageError, LeverageUpEvent, Venue,
+};
+use leverage::aggregator::{MockAggregator, MockAggregatorClient};
+use leverage::aqua::{MockAquaPool, MockAquaRouter, MockAquaRouterClient};
+use leverage::comet::{MockComet, MockCometClient, STROOP};
+use leverage::phoenix::{MockPhoenixFactory, MockPhoenixFactoryClient, MockPhoenixPool};
+use leverage::pool::{FlashLoan, Request};
+use leverage::router::{MockPair, MockRouter, MockRouterClient};
+use soroban_sdk::{
+    auth::{Context, ContractContext},
+    contracttype, symbol_short,
+    testutils::{Address as _, Events, Ledger},
+    vec, Address, BytesN, Error, IntoVal, Symbol, TryFromVal,
+};
+
+#[test]
+fn test_leverage_up_lands_on_target_c_factor() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    // Owner collateral already sitting in the contract, plus a flash loan
+    // sized for ~130%
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+
+    // Flash loan repaid in full
+    assert_eq!(test_env.balance(&test_env.collateral, &lender), 3_250 * SCALAR_7);
+
+    // Position sits on the target, within the swap fee
+    let (collateral, debt) = test_env.position();
+    assert!(collateral > 4_250 * SCALAR_7);
+    assert!(debt > 0);
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor");
+
+    // Nothing left idle in the contract
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_leverage_up_and_deleverage_batch_blend_requests() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    // Supply and borrow go in one submit, and only the collateral bought
+    // with the borrow left over after the buyback needs a second
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    let submits = test_env.pool.submits();
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    assert_eq!(test_env.pool.submits() - submits, 2);
+
+    // Repay and withdraw go in one submit
+    let submits = test_env.pool.submits();
+    test_env.flash_loan(&lender, &test_env.debt, 1_000 * SCALAR_7, 5 * SCALAR_7);
+    assert_eq!(test_env.pool.submits() - submits, 1);
+}
+
+#[test]
+fn test_leverage_up_small_flash_stays_above_target() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 1_000 * SCALAR_7, 0);
+
+    // Borrowed to the target, surplus collateral resupplied on top
+    assert_eq!(test_env.balance(&test_env.collateral, &lender), 1_000 * SCALAR_7);
+    let c_factor = test_env.c_factor();
+    assert!(c_factor > TARGET_C_FACTOR);
+    assert!(c_factor < 20_000);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_leverage_up_with_existing_position() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    let (_, debt_before) = test_env.position();
+
+    // Interest accrues on both sides, then more collateral comes in
+    test_env
+        .pool
+        .set_rates(&test_env.collateral, &1_020_000_000_000, &1_000_000_000_000);
+    test_env
+        .pool
+        .set_rates(&test_env.debt, &1_000_000_000_000, &1_050_000_000_000);
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 500 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 1_000 * SCALAR_7, 10 * SCALAR_7);
+
+    // Flash loan and fee repaid, position back near the target
+    assert_eq!(test_env.balance(&test_env.collateral, &lender), 4_260 * SCALAR_7);
+    let (_, debt_after) = test_env.position();
+    assert!(debt_after > debt_before);
+    let c_factor = test_env.c_factor();
+    assert!(c_factor >= TARGET_C_FACTOR - 50);
+    assert!(c_factor < 14_000);
+}
+
+#[test]
+fn test_leverage_up_target_below_liquidation_fails() {
+    // 90% c_factor * 95% l_factor liquidates below ~117%
+    let test_env = setup_leverage_with_config(LeverageConfig {
+        min_c_factor: 11_000,
+        target_c_factor: 11_500,
+        ..Default::default()
+    });
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 3_000 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.collateral, 2_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(2_000 * SCALAR_7), &0);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::TargetUnreachable as u32)))
+    );
+}
+
+#[test]
+fn test_leverage_up_flash_too_large_for_target_fails() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    // 130% on 1k of equity carries ~4.3k of collateral, so a 10k flash loan
+    // cannot be bought back without overshooting
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 11_000 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.collateral, 10_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(10_000 * SCALAR_7), &0);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::TargetUnreachable as u32)))
+    );
+}
+
+#[test]
+fn test_exec_op_unsupported_token_fails() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+    test_env.leverage.set_flash_lender(&lender, &true);
+
+    let result = test_env
+        .leverage
+        .try_begin_flash_loan(&lender, &test_env.reward, &(100 * SCALAR_7));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::UnsupportedToken as u32)))
+    );
+}
+
+#[test]
+fn test_deleverage_lands_on_target_c_factor() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    let (collateral_before, debt_before) = test_env.position();
+
+    // Collateral grows while debt stays flat, pushing the position above target
+    test_env
+        .pool
+        .set_rates(&test_env.collateral, &1_100_000_000_000, &1_000_000_000_000);
+    assert!(test_env.c_factor() > TARGET_C_FACTOR + 1_000);
+
+    let lender_balance = test_env.balance(&test_env.debt, &lender);
+    test_env.flash_loan(&lender, &test_env.debt, 1_000 * SCALAR_7, 5 * SCALAR_7);
+
+    // Flash loan and fee repaid
+    assert_eq!(
+        test_env.balance(&test_env.debt, &lender),
+        lender_balance + 1_005 * SCALAR_7
+    );
+
+    // Remaining position is priced back onto the target
+    let (collateral_after, debt_after) = test_env.position();
+    assert_eq!(debt_after, debt_before - 1_000 * SCALAR_7);
+    assert!(collateral_after < collateral_before * 11 / 10);
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 1, "c-factor");
+
+    // Surplus collateral goes to the owner, nothing idle in the contract
+    assert!(test_env.balance(&test_env.collateral, &test_env.owner) > 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_deleverage_full_repayment_withdraws_everything() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    let (_, debt) = test_env.position();
+
+    let lender_balance = test_env.balance(&test_env.debt, &lender);
+    test_env.flash_loan(&lender, &test_env.debt, debt, 0);
+
+    assert_eq!(test_env.balance(&test_env.debt, &lender), lender_balance + debt);
+    assert_eq!(test_env.position(), (0, 0));
+
+    // Owner gets back their collateral less two rounds of swap fees, with no
+    // collateral sold beyond the repayment
+    let owner_collateral = test_env.balance(&test_env.collateral, &test_env.owner);
+    assert!(owner_collateral > 950 * SCALAR_7);
+    assert!(owner_collateral < 1_000 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_deleverage_below_target_fails() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+
+    // Collateral price drops ~5%, so a small repayment cannot restore the
+    // target and still free enough collateral to repay the flash loan
+    test_env.oracle.set_price(&test_env.collateral, &SCALAR_7);
+    test_env.mint(&test_env.debt, &test_env.leverage.address, 100 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.debt, 100 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.debt, &(100 * SCALAR_7), &0);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::TargetUnreachable as u32)))
+    );
+}
+
+#[test]
+fn test_open_position_lands_on_target_leverage() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    // 3x leverage is a 150% c-factor
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // Debt is 2x the collateral at oracle prices
+    let (collateral, debt) = test_env.position();
+    assert_eq!(debt, 2_100 * SCALAR_7);
+    assert!(collateral > 2_990 * SCALAR_7);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+
+    // Everything went into the position
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.owner), 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_open_position_adds_to_existing_position() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_500 * SCALAR_7);
+
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    test_env
+        .leverage
+        .open_position(&(500 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let (_, debt) = test_env.position();
+    assert_eq!(debt, 3_150 * SCALAR_7);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+}
+
+#[test]
+fn test_open_position_slippage_exceeded_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    // Oracle has collateral ~5% cheaper than the pair does
+    test_env.oracle.set_price(&test_env.collateral, &SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::SlippageExceeded as u32)))
+    );
+    assert_eq!(test_env.position(), (0, 0));
+}
+
+#[test]
+fn test_open_position_invalid_leverage_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &10_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::BadRequest as u32)))
+    );
+
+    // 10x is a ~111% c-factor, below where Blend liquidates
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &100_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::TargetUnreachable as u32)))
+    );
+}
+
+#[test]
+fn test_open_position_beyond_reachable_leverage_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 2_000 * SCALAR_7);
+
+    // 6x is a 120% c-factor, above the ~117% Blend liquidates at but only
+    // reached as the limit of rounds borrowing down to it
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &60_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::LeverageTooHigh as u32)))
+    );
+    assert_eq!(test_env.position(), (0, 0));
+
+    // 4.5x is within the ~4.6x the rounds reach
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &45_000, &Some(100), &None);
+    assert_approx_eq_bps(test_env.c_factor(), 12_857, 50, "c-factor");
+}
+
+#[test]
+fn test_open_position_default_slippage() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &None, &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+
+    // The ~4% oracle gap is outside the 1% default but inside 5% passed in
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env.oracle.set_price(&test_env.collateral, &10_100_000);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &20_000, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::SlippageExceeded as u32)))
+    );
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &20_000, &Some(MAX_SLIPPAGE_BPS), &None);
+}
+
+#[test]
+fn test_missing_oracle_price_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    test_env.oracle.set_price(&test_env.debt, &0);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::OraclePriceUnavailable as u32)))
+    );
+}
+
+#[test]
+fn test_stale_oracle_price_fails() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    // Prices were set at setup, 16 minutes ago
+    env.ledger().set_timestamp(env.ledger().timestamp() + 960);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::StalePrice as u32)))
+    );
+
+    // A longer limit accepts them again
+    test_env.leverage.set_max_price_age(&3_600);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let result = test_env.leverage.try_set_max_price_age(&0);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+}
+
+#[test]
+fn test_open_position_swap_limits_fail() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    // Above the configured maximum
+    let result = test_env.leverage.try_open_position(
+        &(1_000 * SCALAR_7),
+        &30_000,
+        &Some(MAX_SLIPPAGE_BPS + 1),
+        &None,
+    );
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+
+    // Deadline already passed
+    let now = test_env.env.ledger().timestamp();
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &None, &Some(now - 1));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::DeadlineExpired as u32)))
+    );
+}
+
+#[test]
+fn test_close_position_returns_collateral_to_owner() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let returned = test_env.leverage.close_position(&(950 * SCALAR_7), &None, &None);
+
+    assert_eq!(test_env.position(), (0, 0));
+    let owner_collateral = test_env.balance(&test_env.collateral, &test_env.owner);
+    assert_eq!(owner_collateral, returned);
+    // Initial collateral less the swap fees on the way in and out
+    assert!(owner_collateral > 950 * SCALAR_7);
+    assert!(owner_collateral < 1_000 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_close_position_after_interest_accrues() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    test_env
+        .pool
+        .set_rates(&test_env.debt, &1_000_000_000_000, &1_010_000_000_000);
+
+    test_env.leverage.close_position(&0, &None, &None);
+
+    assert_eq!(test_env.position(), (0, 0));
+    let owner_collateral = test_env.balance(&test_env.collateral, &test_env.owner);
+    assert!(owner_collateral > 930 * SCALAR_7);
+    assert!(owner_collateral < 980 * SCALAR_7);
+}
+
+#[test]
+fn test_close_position_near_liquidation() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+
+    // Collateral price drops ~7%, leaving the position within 5% of the
+    // ~117% Blend liquidates at
+    test_env.oracle.set_price(&test_env.collateral, &9_800_000);
+    assert!(test_env.c_factor() < 12_196);
+
+    // Too close for rounds, so the owner fronts the debt and gets it back
+    let (_, debt) = test_env.position();
+    test_env.mint(&test_env.debt, &test_env.owner, debt + SCALAR_7);
+    let returned = test_env
+        .leverage
+        .close_position(&0, &Some(MAX_SLIPPAGE_BPS), &None);
+
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(returned > 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.owner), returned);
+    assert!(test_env.balance(&test_env.debt, &test_env.owner) >= debt + SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_close_position_below_min_out_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    let position = test_env.position();
+
+    let result = test_env.leverage.try_close_position(&(1_000 * SCALAR_7), &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InsufficientSwapOutput as u32)))
+    );
+    assert_eq!(test_env.position(), position);
+}
+
+#[test]
+fn test_reduce_leverage_steps_down_to_override() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    let (collateral_before, debt_before) = test_env.position();
+
+    test_env.leverage.reduce_leverage(&20_000, &None, &None);
+
+    let (collateral, debt) = test_env.position();
+    assert!(collateral < collateral_before);
+    assert!(debt < debt_before);
+    assert_approx_eq_bps(test_env.c_factor(), 20_000, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+
+    // And again in a smaller step
+    test_env.leverage.reduce_leverage(&25_000, &None, &None);
+    assert_approx_eq_bps(test_env.c_factor(), 25_000, 50, "c-factor");
+}
+
+#[test]
+fn test_reduce_leverage_near_liquidation() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+
+    // Within 5% of the ~117% Blend liquidates at
+    test_env.oracle.set_price(&test_env.collateral, &9_800_000);
+    assert!(test_env.c_factor() < 12_196);
+
+    let (_, debt) = test_env.position();
+    test_env.mint(&test_env.debt, &test_env.owner, debt);
+    test_env
+        .leverage
+        .reduce_leverage(&15_000, &Some(MAX_SLIPPAGE_BPS), &None);
+
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &test_env.owner) >= debt);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_reduce_leverage_above_current_ratio_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // Already at ~150%, so 140% would add leverage
+    let result = test_env.leverage.try_reduce_leverage(&14_000, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::AlreadyAtTarget as u32)))
+    );
+}
+
+#[test]
+fn test_get_position_reports_ratios() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let info = test_env.leverage.get_position();
+    let (collateral, debt) = test_env.position();
+    assert_eq!(info.collateral, collateral);
+    assert_eq!(info.debt, debt);
+    assert_eq!(info.collateral_value, collateral * COLLATERAL_PRICE / SCALAR_7);
+    assert_eq!(info.debt_value, debt * DEBT_PRICE / SCALAR_7);
+    assert_approx_eq_bps(info.c_factor, 15_000, 50, "c-factor");
+    assert_approx_eq_bps(info.leverage, 30_000, 100, "leverage");
+
+    // 150% * 90% c_factor * 95% l_factor
+    assert_approx_eq_bps(info.health_factor, 12_825_000, 50, "health factor");
+    // Collateral price falls by the health factor before liquidation
+    assert_approx_eq_bps(
+        info.liquidation_price,
+        COLLATERAL_PRICE * SCALAR_7 / info.health_factor,
+        1,
+        "liquidation price",
+    );
+}
+
+#[test]
+fn test_get_position_empty() {
+    let test_env = setup_leverage();
+
+    let info = test_env.leverage.get_position();
+    assert_eq!(info.collateral, 0);
+    assert_eq!(info.debt, 0);
+    assert_eq!(info.c_factor, i128::MAX);
+    assert_eq!(info.health_factor, i128::MAX);
+    assert_eq!(info.leverage, i128::MAX);
+    assert_eq!(info.liquidation_price, 0);
+}
+
+#[test]
+fn test_rebalance_levers_up_above_band() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert!(test_env.c_factor() > MAX_C_FACTOR);
+
+    // Levering up needs no auth or capital from the keeper
+    let keeper = Address::generate(&test_env.env);
+    test_env.env.set_auths(&[]);
+    test_env.leverage.rebalance(&keeper);
+
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_rebalance_delevers_below_band() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    // ~4.33x is a 130% c-factor
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+
+    // Collateral price drops ~10%
+    test_env.oracle.set_price(&test_env.collateral, &9_500_000);
+    assert!(test_env.c_factor() < MIN_C_FACTOR);
+
+    // The keeper fronts the repayment and gets it back in full
+    let keeper = Address::generate(&test_env.env);
+    test_env.mint(&test_env.debt, &keeper, 5_000 * SCALAR_7);
+    test_env.leverage.rebalance(&keeper);
+
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.debt, &keeper), 5_000 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_rebalance_inside_band_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+
+    let keeper = Address::generate(&test_env.env);
+    let result = test_env.leverage.try_rebalance(&keeper);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::AlreadyAtTarget as u32)))
+    );
+}
+
+#[test]
+fn test_set_swap_router_moves_swaps_to_new_router() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // Second router with its own, equally priced pair
+    let router = MockRouterClient::new(env, &env.register(MockRouter, ()));
+    let pair = env.register(MockPair, (test_env.collateral.clone(), test_env.debt.clone()));
+    router.add_pair(&test_env.collateral, &test_env.debt, &pair);
+    test_env.mint(&test_env.collateral, &pair, PAIR_COLLATERAL_RESERVE);
+    test_env.mint(&test_env.debt, &pair, PAIR_DEBT_RESERVE);
+
+    test_env.leverage.set_swap_router(&router.address);
+    assert_eq!(env.auths()[0].0, test_env.owner);
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // Only the new pair traded
+    assert!(test_env.balance(&test_env.debt, &pair) > PAIR_DEBT_RESERVE);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.pair), PAIR_DEBT_RESERVE);
+}
+
+#[test]
+fn test_set_target_c_factor_outside_band_fails() {
+    let test_env = setup_leverage();
+
+    test_env.leverage.set_target_c_factor(&MIN_C_FACTOR);
+
+    let result = test_env.leverage.try_set_target_c_factor(&(MAX_C_FACTOR + 1));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+}
+
+#[test]
+fn test_set_c_factor_band_moves_target_limits() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // Widen the band, then move the target into the new part of it
+    test_env.leverage.set_c_factor_band(&11_500, &16_000);
+    assert_eq!(env.auths()[0].0, test_env.owner);
+    test_env.leverage.set_target_c_factor(&15_000);
+
+    // The band still has to hold the target
+    let result = test_env.leverage.try_set_c_factor_band(&15_500, &16_000);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+}
+
+#[test]
+fn test_address_setters_reject_invalid_addresses() {
+    let test_env = setup_leverage();
+    let invalid_config = Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)));
+
+    let result = test_env.leverage.try_set_swap_router(&test_env.leverage.address);
+    assert_eq!(result.err(), invalid_config);
+
+    // Rewards cannot come in a token the position already holds
+    let result = test_env.leverage.try_set_reward_token(&test_env.debt);
+    assert_eq!(result.err(), invalid_config);
+    let result = test_env.leverage.try_set_reward_token(&test_env.collateral);
+    assert_eq!(result.err(), invalid_config);
+    let result = test_env.leverage.try_set_reward_token(&test_env.leverage.address);
+    assert_eq!(result.err(), invalid_config);
+}
+
+#[test]
+fn test_set_slippage_limits_emits_event() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    test_env.leverage.set_slippage_limits(&50, &300);
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![
+            env,
+            (
+                test_env.leverage.address.clone(),
+                (symbol_short!("config"), symbol_short!("slippage")).into_val(env),
+                (50i128, 300i128).into_val(env),
+            ),
+        ]
+    );
+
+    // Calls are now held to the new maximum
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(400), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+
+    // The default has to stay inside the maximum
+    let result = test_env.leverage.try_set_slippage_limits(&400, &300);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+}
+
+#[test]
+fn test_setters_require_owner() {
+    let test_env = setup_leverage();
+    test_env.env.set_auths(&[]);
+
+    let reward = Address::generate(&test_env.env);
+    assert!(test_env.leverage.try_set_reward_token(&reward).is_err());
+    assert!(test_env.leverage.try_set_target_c_factor(&TARGET_C_FACTOR).is_err());
+    assert!(test_env.leverage.try_propose_owner(&reward).is_err());
+}
+
+#[test]
+fn test_ownership_transfer_takes_two_steps() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    let new_owner = Address::generate(env);
+
+    test_env.leverage.propose_owner(&new_owner);
+    assert_eq!(env.auths()[0].0, test_env.owner);
+
+    // The proposed owner has to accept
+    test_env.leverage.accept_owner();
+    assert_eq!(env.auths()[0].0, new_owner);
+
+    // Owner calls now need the new owner
+    test_env.leverage.set_target_c_factor(&MAX_C_FACTOR);
+    assert_eq!(env.auths()[0].0, new_owner);
+
+    // Nothing left to accept
+    let result = test_env.leverage.try_accept_owner();
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::BadRequest as u32)))
+    );
+}
+
+/// `Config` as written by schema version 1, before the oracle, rebalance
+/// band and slippage limits
+#[derive(Clone)]
+#[contracttype]
+struct ConfigV1 {
+    owner: Address,
+    blend_pool: Address,
+    collateral_asset: Address,
+    debt_asset: Address,
+    reward_token: Address,
+    swap_router: Address,
+    target_c_factor: i128,
+}
+
+#[contracttype]
+enum DataKey {
+    Config,
+    SchemaVersion,
+}
+
+#[test]
+fn test_version() {
+    let test_env = setup_leverage();
+    assert_eq!(test_env.leverage.version(), 2);
+}
+
+#[test]
+fn test_upgrade_requires_owner() {
+    let test_env = setup_leverage();
+    test_env.env.set_auths(&[]);
+
+    let hash = BytesN::from_array(&test_env.env, &[0; 32]);
+    assert!(test_env.leverage.try_upgrade(&hash).is_err());
+}
+
+#[test]
+fn test_config_v1_migrated_on_first_call() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // Roll storage back to the version 1 layout
+    env.as_contract(&test_env.leverage.address, || {
+        let storage = env.storage().instance();
+        storage.set(
+            &DataKey::Config,
+            &ConfigV1 {
+                owner: test_env.owner.clone(),
+                blend_pool: test_env.pool.address.clone(),
+                collateral_asset: test_env.collateral.clone(),
+                debt_asset: test_env.debt.clone(),
+                reward_token: test_env.reward.clone(),
+                swap_router: test_env.router.address.clone(),
+                target_c_factor: 15_000,
+            },
+        );
+        storage.remove(&DataKey::SchemaVersion);
+    });
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &None, &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+
+    env.as_contract(&test_env.leverage.address, || {
+        let storage = env.storage().instance();
+        let config: Config = storage.get(&DataKey::Config).unwrap();
+        assert_eq!(config.oracle, test_env.oracle.address);
+        assert_eq!(config.min_c_factor, 14_000);
+        assert_eq!(config.target_c_factor, 15_000);
+        assert_eq!(config.max_c_factor, 16_000);
+        assert_eq!(config.default_slippage_bps, 100);
+        assert_eq!(config.max_slippage_bps, 500);
+        assert_eq!(storage.get::<_, u32>(&DataKey::SchemaVersion), Some(2));
+    });
+}
+
+#[test]
+fn test_pause_blocks_leverage_up_and_claim() {
+    let test_env = setup_leverage();
+    let guardian = Address::generate(&test_env.env);
+    test_env.leverage.set_guardian(&guardian);
+
+    test_env.leverage.pause(&guardian);
+    assert!(test_env.leverage.paused());
+
+    let paused = Some(Ok(Error::from_contract_error(LeverageError::Paused as u32)));
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &None, &None);
+    assert_eq!(result.err(), paused);
+
+    let lender = Address::generate(&test_env.env);
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.collateral, 1_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(1_000 * SCALAR_7), &0);
+    assert_eq!(result.err(), paused);
+
+    let result = test_env.leverage.try_claim(&test_env.owner);
+    assert_eq!(result.err(), Some(Ok(LeverageError::Paused)));
+}
+
+#[test]
+fn test_pause_allows_deleverage_and_close() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    test_env.leverage.pause(&test_env.owner);
+
+    let lender = Address::generate(&test_env.env);
+    test_env.flash_loan(&lender, &test_env.debt, 100 * SCALAR_7, 0);
+    test_env.leverage.reduce_leverage(&17_000, &None, &None);
+    test_env.leverage.close_position(&0, &None, &None);
+    assert_eq!(test_env.position(), (0, 0));
+}
+
+#[test]
+fn test_guardian_cannot_unpause() {
+    let test_env = setup_leverage();
+    let guardian = Address::generate(&test_env.env);
+    test_env.leverage.set_guardian(&guardian);
+    test_env.leverage.pause(&guardian);
+
+    test_env.env.set_auths(&[]);
+    assert!(test_env.leverage.try_unpause().is_err());
+    test_env.env.mock_all_auths();
+
+    test_env.leverage.unpause();
+    assert_eq!(test_env.env.auths()[0].0, test_env.owner);
+    assert!(!test_env.leverage.paused());
+
+    // Nobody else can pause
+    let stranger = Address::generate(&test_env.env);
+    let result = test_env.leverage.try_pause(&stranger);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::Unauthorized as u32)))
+    );
+}
+
+#[test]
+fn test_exec_op_emits_leverage_up_event() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 5 * SCALAR_7);
+
+    let event: LeverageUpEvent = test_env.last_event(symbol_short!("lever_up"));
+    let (collateral, debt) = test_env.position();
+    assert_eq!(event.flash_amount, 3_250 * SCALAR_7);
+    assert_eq!(event.fee, 5 * SCALAR_7);
+    assert_eq!(event.supplied, 4_250 * SCALAR_7 + event.swap_out - 3_255 * SCALAR_7);
+    assert_eq!(event.borrowed, debt);
+    assert_eq!(event.swap_in, event.borrowed);
+    assert_eq!((event.collateral, event.debt), (collateral, debt));
+}
+
+#[test]
+fn test_exec_op_emits_deleverage_event() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    test_env
+        .pool
+        .set_rates(&test_env.collateral, &1_100_000_000_000, &1_000_000_000_000);
+    let (collateral_before, _) = test_env.position();
+
+    test_env.flash_loan(&lender, &test_env.debt, 1_000 * SCALAR_7, 5 * SCALAR_7);
+
+    let event: DeleverageEvent = test_env.last_event(symbol_short!("delever"));
+    let (collateral, debt) = test_env.position();
+    assert_eq!(event.flash_amount, 1_000 * SCALAR_7);
+    assert_eq!(event.fee, 5 * SCALAR_7);
+    assert_eq!(event.repaid, 1_000 * SCALAR_7);
+    assert_eq!(event.swap_out, 1_005 * SCALAR_7);
+    assert!(event.swap_in <= event.withdrawn);
+    assert_approx_eq_bps(event.withdrawn, collateral_before - collateral, 1, "withdrawn");
+    assert_eq!((event.collateral, event.debt), (collateral, debt));
+}
+
+#[test]
+fn test_open_and_close_position_emit_events() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let event: LeverageUpEvent = test_env.last_event(symbol_short!("lever_up"));
+    let (collateral, debt) = test_env.position();
+    assert_eq!(event.flash_amount, 0);
+    assert_eq!(event.supplied, collateral);
+    assert_eq!(event.borrowed, debt);
+    assert_eq!((event.collateral, event.debt), (collateral, debt));
+
+    let collateral_out = test_env.leverage.close_position(&0, &None, &None);
+
+    let event: DeleverageEvent = test_env.last_event(symbol_short!("delever"));
+    assert_eq!(event.repaid, debt);
+    assert!(event.withdrawn >= collateral_out);
+    assert_eq!((event.collateral, event.debt), (0, 0));
+}
+
+#[test]
+fn test_claim_emits_event() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.reward, &test_env.pool.address, 50 * SCALAR_7);
+    test_env.pool.set_emissions(&(50 * SCALAR_7));
+
+    test_env.leverage.claim(&test_env.owner);
+
+    let event: ClaimEvent = test_env.last_event(symbol_short!("claim"));
+    assert_eq!(event.to, test_env.owner);
+    assert_eq!(event.amount, 50 * SCALAR_7);
+}
+
+#[test]
+fn test_open_position_below_band_floor_fails() {
+    let test_env = setup_leverage_with_config(LeverageConfig {
+        min_c_factor: 13_000,
+        target_c_factor: 13_500,
+        ..Default::default()
+    });
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+
+    // 4.5x is a ~129% c-factor, which Blend allows but the band does not
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &45_000, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::UnhealthyPosition as u32)))
+    );
+}
+
+#[test]
+fn test_nothing_to_act_on_fails() {
+    let test_env = setup_leverage();
+    let zero_balance = Some(Ok(Error::from_contract_error(LeverageError::ZeroBalance as u32)));
+
+    let keeper = Address::generate(&test_env.env);
+    assert_eq!(test_env.leverage.try_rebalance(&keeper).err(), zero_balance);
+
+    let lender = Address::generate(&test_env.env);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &0, &0);
+    assert_eq!(result.err(), zero_balance);
+}
+
+#[test]
+fn test_pool_frozen_fails_with_pool_frozen() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env.pool.set_status(&pool::STATUS_ON_ICE);
+
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::PoolFrozen as u32)))
+    );
+}
+
+#[test]
+fn test_router_errors_are_translated() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 2_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // A router without the pair
+    let router = MockRouterClient::new(env, &env.register(MockRouter, ()));
+    test_env.leverage.set_swap_router(&router.address);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::PairNotFound as u32)))
+    );
+
+    // A pair too shallow to buy back the debt
+    let pair = env.register(MockPair, (test_env.collateral.clone(), test_env.debt.clone()));
+    router.add_pair(&test_env.collateral, &test_env.debt, &pair);
+    test_env.mint(&test_env.collateral, &pair, 100 * SCALAR_7);
+    test_env.mint(&test_env.debt, &pair, 100 * SCALAR_7);
+    let result = test_env.leverage.try_close_position(&0, &None, &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InsufficientLiquidity as u32)))
+    );
+}
+
+#[test]
+fn test_exec_op_outside_started_flash_loan_fails() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+    let unauthorized = Some(Ok(Error::from_contract_error(LeverageError::Unauthorized as u32)));
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 2_000 * SCALAR_7);
+
+    // Nothing started
+    test_env.leverage.set_flash_lender(&lender, &true);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(1_000 * SCALAR_7), &0);
+    assert_eq!(result.err(), unauthorized);
+
+    // Different amount, token or lender than started
+    test_env.leverage.begin_flash_loan(&lender, &test_env.collateral, &(1_000 * SCALAR_7));
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(1_500 * SCALAR_7), &0);
+    assert_eq!(result.err(), unauthorized);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.debt, &(1_000 * SCALAR_7), &0);
+    assert_eq!(result.err(), unauthorized);
+    let other = Address::generate(&test_env.env);
+    let result = test_env
+        .leverage
+        .try_exec_op(&other, &test_env.collateral, &(1_000 * SCALAR_7), &0);
+    assert_eq!(result.err(), unauthorized);
+
+    // The started loan runs once
+    test_env
+        .leverage
+        .exec_op(&lender, &test_env.collateral, &(1_000 * SCALAR_7), &0);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(1_000 * SCALAR_7), &0);
+    assert_eq!(result.err(), unauthorized);
+}
+
+#[test]
+fn test_begin_flash_loan_requires_allowed_lender() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+
+    let result = test_env
+        .leverage
+        .try_begin_flash_loan(&lender, &test_env.collateral, &(1_000 * SCALAR_7));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::Unauthorized as u32)))
+    );
+
+    // The Blend pool is always allowed
+    test_env.leverage.begin_flash_loan(
+        &test_env.pool.address,
+        &test_env.collateral,
+        &(1_000 * SCALAR_7),
+    );
+
+    // Removed lenders are no longer allowed
+    test_env.leverage.set_flash_lender(&lender, &true);
+    test_env.leverage.set_flash_lender(&lender, &false);
+    let result = test_env
+        .leverage
+        .try_begin_flash_loan(&lender, &test_env.collateral, &(1_000 * SCALAR_7));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::Unauthorized as u32)))
+    );
+}
+
+#[test]
+fn test_blend_flash_loan_levers_up_in_one_submission() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &15_000, &Some(100), &None);
+    let (collateral_before, debt_before) = test_env.position();
+
+    // Borrow 500 debt, buy 450 collateral with it and supply that
+    test_env.blend_flash_loan(500 * SCALAR_7, 450 * SCALAR_7);
+    let event: LeverageUpEvent = test_env.last_event(symbol_short!("lever_up"));
+
+    let (collateral, debt) = test_env.position();
+    assert_eq!(collateral - collateral_before, 450 * SCALAR_7);
+    assert_eq!(debt - debt_before, 500 * SCALAR_7);
+
+    // The whole loan is sold, nothing borrowed is left idle, and collateral
+    // bought beyond the supply goes to the owner
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(
+        test_env.balance(&test_env.collateral, &test_env.owner),
+        event.swap_out - 450 * SCALAR_7
+    );
+    assert!(event.swap_out > 450 * SCALAR_7);
+    assert_eq!(event.flash_amount, 500 * SCALAR_7);
+    assert_eq!(event.swap_in, 500 * SCALAR_7);
+    assert_eq!(event.supplied, 450 * SCALAR_7);
+}
+
+#[test]
+fn test_blend_flash_loan_supply_above_swap_output_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &15_000, &Some(100), &None);
+
+    // 500 debt buys ~475 collateral, short of the 490 the requests supply
+    let requests = vec![
+        &test_env.env,
+        Request {
+            address: test_env.collateral.clone(),
+            amount: 490 * SCALAR_7,
+            request_type: 2,
+        },
+    ];
+    test_env.check_blend_flash_loan(500 * SCALAR_7, &requests).unwrap();
+    test_env.env.mock_all_auths_allowing_non_root_auth();
+    let result = test_env.pool.try_flash_loan(
+        &test_env.leverage.address,
+        &FlashLoan {
+            amount: 500 * SCALAR_7,
+            asset: test_env.debt.clone(),
+            contract: test_env.leverage.address.clone(),
+        },
+        &requests,
+    );
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InsufficientSwapOutput as u32)))
+    );
+}
+
+#[test]
+fn test_blend_flash_loan_check_auth_rejects_other_requests() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // Only collateral supplies may ride along
+    let requests = vec![
+        env,
+        Request {
+            address: test_env.debt.clone(),
+            amount: 100 * SCALAR_7,
+            request_type: 4,
+        },
+    ];
+    let result = test_env.check_blend_flash_loan(100 * SCALAR_7, &requests);
+    assert_eq!(result.err(), Some(Ok(LeverageError::BadRequest)));
+
+    // Something has to be supplied
+    let result = test_env.check_blend_flash_loan(100 * SCALAR_7, &vec![env]);
+    assert_eq!(result.err(), Some(Ok(LeverageError::ZeroBalance)));
+
+    // Any other call on the contract's behalf
+    let context = Context::Contract(ContractContext {
+        contract: test_env.pool.address.clone(),
+        fn_name: Symbol::new(env, "submit"),
+        args: vec![env],
+    });
+    let result = env.try_invoke_contract_check_auth::<LeverageError>(
+        &test_env.leverage.address,
+        &BytesN::from_array(env, &[0; 32]),
+        ().into_val(env),
+        &vec![env, context],
+    );
+    assert_eq!(result.err(), Some(Ok(LeverageError::Unauthorized)));
+}
+
+#[test]
+fn test_blend_flash_loan_without_check_auth_fails() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &15_000, &Some(100), &None);
+
+    let requests = vec![
+        &test_env.env,
+        Request {
+            address: test_env.collateral.clone(),
+            amount: 450 * SCALAR_7,
+            request_type: 2,
+        },
+    ];
+    let result = test_env.pool.try_flash_loan(
+        &test_env.leverage.address,
+        &FlashLoan {
+            amount: 500 * SCALAR_7,
+            asset: test_env.debt.clone(),
+            contract: test_env.leverage.address.clone(),
+        },
+        &requests,
+    );
+    assert!(result.is_err());
+}
+
+/// Lists a reward/collateral pair on the router, 10 reward to 1 collateral,
+/// and has the pool pay out `emissions` of rewards on the next claim
+fn setup_rewards(test_env: &LeverageTestEnv, emissions: i128) {
+    let env = &test_env.env;
+    let pair = env.register(MockPair, (test_env.reward.clone(), test_env.collateral.clone()));
+    test_env.router.add_pair(&test_env.reward, &test_env.collateral, &pair);
+    test_env.mint(&test_env.reward, &pair, 10_000_000 * SCALAR_7);
+    test_env.mint(&test_env.collateral, &pair, 1_000_000 * SCALAR_7);
+
+    // 0.1 collateral per reward, as the pair prices it
+    test_env.oracle.set_price(&test_env.reward, &1_050_000);
+    test_env.mint(&test_env.reward, &test_env.pool.address, emissions);
+    test_env.pool.set_emissions(&emissions);
+}
+
+#[test]
+fn test_harvest_and_compound_supplies_rewards() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    let (collateral_before, debt_before) = test_env.position();
+    setup_rewards(&test_env, 1_000 * SCALAR_7);
+
+    test_env
+        .leverage
+        .harvest_and_compound(&(99 * SCALAR_7), &false);
+    let event: CompoundEvent = test_env.last_event(symbol_short!("compound"));
+
+    // ~100 collateral for 1000 rewards, all supplied without new debt
+    assert_eq!(event.claimed, 1_000 * SCALAR_7);
+    assert!(event.supplied >= 99 * SCALAR_7);
+    let (collateral, debt) = test_env.position();
+    assert_eq!(collateral - collateral_before, event.supplied);
+    assert_eq!(debt, debt_before);
+    assert_eq!(test_env.balance(&test_env.reward, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_harvest_and_compound_relevers_to_target() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor before");
+    let (_, debt_before) = test_env.position();
+    setup_rewards(&test_env, 5_000 * SCALAR_7);
+
+    test_env
+        .leverage
+        .harvest_and_compound(&(490 * SCALAR_7), &true);
+
+    let (_, debt) = test_env.position();
+    assert!(debt > debt_before);
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor after");
+}
+
+#[test]
+fn test_harvest_and_compound_enforces_min_out() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // Nothing to claim yet
+    let result = test_env
+        .leverage
+        .try_harvest_and_compound(&0, &false);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::ZeroBalance as u32)))
+    );
+
+    setup_rewards(&test_env, 1_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_harvest_and_compound(&(101 * SCALAR_7), &false);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InsufficientSwapOutput as u32)))
+    );
+}
+
+#[test]
+fn test_harvest_and_compound_enforces_oracle_price() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    setup_rewards(&test_env, 1_000 * SCALAR_7);
+    let position = test_env.position();
+
+    // The pair pays ~10% less than the oracle says the rewards are worth
+    test_env.oracle.set_price(&test_env.reward, &1_160_000);
+    let result = test_env.leverage.try_harvest_and_compound(&0, &false);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InsufficientSwapOutput as u32)))
+    );
+    assert_eq!(test_env.position(), position);
+}
+
+#[test]
+fn test_harvest_and_compound_follows_compound_path() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    let (collateral_before, _) = test_env.position();
+
+    // No reward/collateral pair, only reward/USDC and USDC/collateral
+    let admin = Address::generate(env);
+    let usdc = env.register_stellar_asset_contract_v2(admin).address();
+    let reward_pair = env.register(MockPair, (test_env.reward.clone(), usdc.clone()));
+    test_env.router.add_pair(&test_env.reward, &usdc, &reward_pair);
+    test_env.mint(&test_env.reward, &reward_pair, 10_000_000 * SCALAR_7);
+    test_env.mint(&usdc, &reward_pair, 1_000_000 * SCALAR_7);
+    let usdc_pair = env.register(MockPair, (usdc.clone(), test_env.collateral.clone()));
+    test_env.router.add_pair(&usdc, &test_env.collateral, &usdc_pair);
+    test_env.mint(&usdc, &usdc_pair, 1_000_000 * SCALAR_7);
+    test_env.mint(&test_env.collateral, &usdc_pair, 1_000_000 * SCALAR_7);
+    test_env.oracle.set_price(&test_env.reward, &1_050_000);
+    test_env.mint(&test_env.reward, &test_env.pool.address, 1_000 * SCALAR_7);
+    test_env.pool.set_emissions(&(1_000 * SCALAR_7));
+
+    // The path has to take rewards to collateral
+    let result = test_env
+        .leverage
+        .try_set_compound_path(&vec![env, test_env.reward.clone(), usdc.clone()]);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+
+    test_env.leverage.set_compound_path(&vec![
+        env,
+        test_env.reward.clone(),
+        usdc.clone(),
+        test_env.collateral.clone(),
+    ]);
+    test_env
+        .leverage
+        .harvest_and_compound(&(99 * SCALAR_7), &false);
+
+    let (collateral, _) = test_env.position();
+    assert!(collateral - collateral_before >= 99 * SCALAR_7);
+    assert!(test_env.balance(&test_env.reward, &reward_pair) > 10_000_000 * SCALAR_7);
+}
+
+#[test]
+fn test_claim_ids_derived_from_reserve_list() {
+    let test_env = setup_leverage();
+
+    // The debt reserve is listed first, so collateral bTokens are id 3 and
+    // debt dTokens id 0
+    test_env.leverage.claim(&test_env.owner);
+    assert_eq!(test_env.pool.last_claim_ids(), vec![&test_env.env, 3u32, 0u32]);
+
+    // The owner can override them
+    let ids = vec![&test_env.env, 1u32];
+    test_env.leverage.set_claim_ids(&ids);
+    test_env.leverage.claim(&test_env.owner);
+    assert_eq!(test_env.pool.last_claim_ids(), ids);
+}
+
+#[test]
+fn test_exec_op_rejects_swaps_off_the_oracle_price() {
+    let test_env = setup_leverage();
+    let lender = Address::generate(&test_env.env);
+    let slippage_exceeded = Some(Ok(Error::from_contract_error(
+        LeverageError::SlippageExceeded as u32,
+    )));
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 2_000 * SCALAR_7, 0);
+
+    // Pushing debt into the pair makes collateral ~4% dearer than the oracle
+    test_env.mint(&test_env.debt, &test_env.pair, 400_000 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.collateral, 500 * SCALAR_7);
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 500 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.collateral, &(500 * SCALAR_7), &0);
+    assert_eq!(result.err(), slippage_exceeded);
+
+    // And pushing collateral in makes it ~4% cheaper to sell
+    test_env.mint(&test_env.collateral, &test_env.pair, 800_000 * SCALAR_7);
+    test_env.begin_flash_loan(&lender, &test_env.debt, 500 * SCALAR_7);
+    test_env.mint(&test_env.debt, &test_env.leverage.address, 500 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_exec_op(&lender, &test_env.debt, &(500 * SCALAR_7), &0);
+    assert_eq!(result.err(), slippage_exceeded);
+}
+
+#[test]
+fn test_swap_paths_route_through_intermediate_token() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    let admin = Address::generate(env);
+    let xlm = env.register_stellar_asset_contract_v2(admin).address();
+
+    // A router with no direct pair, only debt/XLM and XLM/collateral
+    let router = MockRouterClient::new(env, &env.register(MockRouter, ()));
+    let debt_pair = env.register(MockPair, (test_env.debt.clone(), xlm.clone()));
+    router.add_pair(&test_env.debt, &xlm, &debt_pair);
+    test_env.mint(&test_env.debt, &debt_pair, PAIR_DEBT_RESERVE);
+    test_env.mint(&xlm, &debt_pair, 4 * PAIR_DEBT_RESERVE);
+    let collateral_pair = env.register(MockPair, (xlm.clone(), test_env.collateral.clone()));
+    router.add_pair(&xlm, &test_env.collateral, &collateral_pair);
+    test_env.mint(&xlm, &collateral_pair, 4 * PAIR_DEBT_RESERVE);
+    test_env.mint(&test_env.collateral, &collateral_pair, PAIR_COLLATERAL_RESERVE);
+    test_env.leverage.set_swap_router(&router.address);
+
+    test_env.leverage.set_swap_paths(
+        &vec![env, test_env.debt.clone(), xlm.clone(), test_env.collateral.clone()],
+        &vec![env, test_env.collateral.clone(), xlm.clone(), test_env.debt.clone()],
+    );
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(MAX_SLIPPAGE_BPS), &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &debt_pair) > PAIR_DEBT_RESERVE);
+
+    test_env
+        .leverage
+        .close_position(&0, &Some(MAX_SLIPPAGE_BPS), &None);
+    let (collateral, debt) = test_env.position();
+    assert_eq!((collateral, debt), (0, 0));
+    assert!(test_env.balance(&test_env.collateral, &test_env.owner) > 0);
+}
+
+#[test]
+fn test_set_swap_paths_must_join_the_assets() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    let xlm = Address::generate(env);
+    let invalid_config = Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)));
+
+    // Lever path ending in the debt asset
+    let result = test_env.leverage.try_set_swap_paths(
+        &vec![env, test_env.debt.clone(), xlm.clone(), test_env.debt.clone()],
+        &vec![env, test_env.collateral.clone(), test_env.debt.clone()],
+    );
+    assert_eq!(result.err(), invalid_config);
+
+    // Delever path with a single token
+    let result = test_env.leverage.try_set_swap_paths(
+        &vec![env, test_env.debt.clone(), test_env.collateral.clone()],
+        &vec![env, test_env.collateral.clone()],
+    );
+    assert_eq!(result.err(), invalid_config);
+}
+
+#[test]
+fn test_phoenix_venue_opens_and_closes_position() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // Phoenix pool priced like the Soroswap pair
+    let factory = MockPhoenixFactoryClient::new(env, &env.register(MockPhoenixFactory, ()));
+    let pool = env.register(
+        MockPhoenixPool,
+        (test_env.collateral.clone(), test_env.debt.clone(), 30i128),
+    );
+    factory.add_pool(&test_env.collateral, &test_env.debt, &pool);
+    test_env.mint(&test_env.collateral, &pool, PAIR_COLLATERAL_RESERVE);
+    test_env.mint(&test_env.debt, &pool, PAIR_DEBT_RESERVE);
+    test_env.leverage.set_venue(&Venue::Phoenix(factory.address.clone()));
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &pool) > PAIR_DEBT_RESERVE);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.pair), PAIR_DEBT_RESERVE);
+
+    let collateral_out = test_env.leverage.close_position(&0, &None, &None);
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(collateral_out > 950 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_comet_venue_opens_and_closes_position() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // 80/20 pool pricing collateral at 4 * 2.625M / 10M = 1.05 debt
+    let comet = MockCometClient::new(env, &env.register(MockComet, (STROOP * 3 / 1_000,)));
+    comet.bind(&test_env.collateral, &(STROOP * 8 / 10));
+    comet.bind(&test_env.debt, &(STROOP * 2 / 10));
+    test_env.mint(&test_env.collateral, &comet.address, PAIR_COLLATERAL_RESERVE);
+    test_env.mint(&test_env.debt, &comet.address, PAIR_DEBT_RESERVE / 4);
+    test_env.leverage.set_venue(&Venue::Comet(comet.address.clone()));
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &comet.address) > PAIR_DEBT_RESERVE / 4);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+
+    let collateral_out = test_env.leverage.close_position(&0, &None, &None);
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(collateral_out > 950 * SCALAR_7);
+}
+
+#[test]
+fn test_comet_venue_repays_debt_bought_past_the_ask() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    let comet = MockCometClient::new(env, &env.register(MockComet, (STROOP * 3 / 1_000,)));
+    comet.bind(&test_env.collateral, &(STROOP * 8 / 10));
+    comet.bind(&test_env.debt, &(STROOP * 2 / 10));
+    test_env.mint(&test_env.collateral, &comet.address, PAIR_COLLATERAL_RESERVE);
+    test_env.mint(&test_env.debt, &comet.address, PAIR_DEBT_RESERVE / 4);
+    test_env.leverage.set_venue(&Venue::Comet(comet.address.clone()));
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    // Comet sells its quoted input for the exact output, which can buy a
+    // little more debt than asked for
+    test_env.leverage.reduce_leverage(&20_000, &None, &None);
+    assert_approx_eq_bps(test_env.c_factor(), 20_000, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_soroswap_aggregator_venue_opens_and_closes_position() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // The aggregator trades the router's pairs
+    let aggregator = MockAggregatorClient::new(
+        env,
+        &env.register(MockAggregator, (test_env.router.address.clone(),)),
+    );
+    test_env
+        .leverage
+        .set_venue(&Venue::SoroswapAggregator(aggregator.address.clone()));
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    let swaps = aggregator.swaps();
+    assert!(swaps > 0);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &test_env.pair) > PAIR_DEBT_RESERVE);
+
+    let collateral_out = test_env.leverage.close_position(&0, &None, &None);
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(collateral_out > 950 * SCALAR_7);
+    assert!(aggregator.swaps() > swaps);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+}
+
+#[test]
+fn test_aqua_venue_trades_through_the_best_pool() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    // A deep pool priced like the Soroswap pair, and a shallow one charging
+    // 1% that should never win a hop
+    let router = MockAquaRouterClient::new(env, &env.register(MockAquaRouter, ()));
+    let deep = env.register(
+        MockAquaPool,
+        (test_env.collateral.clone(), test_env.debt.clone(), 30u128),
+    );
+    test_env.mint(&test_env.collateral, &deep, PAIR_COLLATERAL_RESERVE);
+    test_env.mint(&test_env.debt, &deep, PAIR_DEBT_RESERVE);
+    let shallow = env.register(
+        MockAquaPool,
+        (test_env.collateral.clone(), test_env.debt.clone(), 100u128),
+    );
+    test_env.mint(&test_env.collateral, &shallow, 10_000 * SCALAR_7);
+    test_env.mint(&test_env.debt, &shallow, 10_500 * SCALAR_7);
+    router.add_pool(&shallow);
+    router.add_pool(&deep);
+    test_env.leverage.set_venue(&Venue::Aqua(router.address.clone()));
+
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+    assert!(test_env.balance(&test_env.debt, &deep) > PAIR_DEBT_RESERVE);
+    assert_eq!(test_env.balance(&test_env.debt, &shallow), 10_500 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.pair), PAIR_DEBT_RESERVE);
+
+    // Aqua sells the quoted input for exact outputs, and what that buys past
+    // the ask is repaid rather than left in the contract
+    test_env.leverage.reduce_leverage(&20_000, &None, &None);
+    assert_approx_eq_bps(test_env.c_factor(), 20_000, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+
+    let collateral_out = test_env.leverage.close_position(&0, &None, &None);
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(collateral_out > 950 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.collateral, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+    assert_eq!(test_env.balance(&test_env.collateral, &shallow), 10_000 * SCALAR_7);
+}
+
+#[test]
+fn test_set_venue_checks_the_venue() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+
+    let result = test_env
+        .leverage
+        .try_set_venue(&Venue::Comet(test_env.leverage.address.clone()));
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+
+    // A Comet pool without the debt asset cannot trade the pair
+    let comet = MockCometClient::new(env, &env.register(MockComet, (STROOP * 3 / 1_000,)));
+    comet.bind(&test_env.collateral, &(STROOP / 2));
+    comet.bind(&test_env.reward, &(STROOP / 2));
+    test_env.leverage.set_venue(&Venue::Comet(comet.address.clone()));
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::PairNotFound as u32)))
+    );
+
+    // Back on Soroswap's router
+    test_env.leverage.set_venue(&Venue::SoroswapRouter);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+}
+
+/// Debt held by the XLM/debt pair of `add_xlm_route`
+const XLM_ROUTE_DEBT_RESERVE: i128 = 21_000 * SCALAR_7;
+
+/// Lists shallow collateral/XLM and XLM/debt pairs on the default router,
+/// pricing collateral ~5% above the direct pair, and returns the collateral
+/// to debt route through them with the XLM/debt pair
+fn add_xlm_route(test_env: &LeverageTestEnv) -> (soroban_sdk::Vec<Address>, Address) {
+    let env = &test_env.env;
+    let admin = Address::generate(env);
+    let xlm = env.register_stellar_asset_contract_v2(admin).address();
+
+    let collateral_pair = env.register(MockPair, (test_env.collateral.clone(), xlm.clone()));
+    test_env.router.add_pair(&test_env.collateral, &xlm, &collateral_pair);
+    test_env.mint(&test_env.collateral, &collateral_pair, 20_000 * SCALAR_7);
+    test_env.mint(&xlm, &collateral_pair, 84_000 * SCALAR_7);
+    let debt_pair = env.register(MockPair, (xlm.clone(), test_env.debt.clone()));
+    test_env.router.add_pair(&xlm, &test_env.debt, &debt_pair);
+    test_env.mint(&xlm, &debt_pair, 80_000 * SCALAR_7);
+    test_env.mint(&test_env.debt, &debt_pair, XLM_ROUTE_DEBT_RESERVE);
+
+    let route = vec![env, test_env.collateral.clone(), xlm, test_env.debt.clone()];
+    (route, debt_pair)
+}
+
+#[test]
+fn test_delever_routes_split_close_position() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let (route, debt_pair) = add_xlm_route(&test_env);
+    let direct = vec![env, test_env.collateral.clone(), test_env.debt.clone()];
+    test_env.leverage.set_delever_routes(&vec![env, direct, route]);
+    let pair_debt = test_env.balance(&test_env.debt, &test_env.pair);
+
+    test_env
+        .leverage
+        .close_position(&0, &Some(MAX_SLIPPAGE_BPS), &None);
+
+    // Both routes paid out debt
+    assert_eq!(test_env.position(), (0, 0));
+    assert!(test_env.balance(&test_env.debt, &test_env.pair) < pair_debt);
+    assert!(test_env.balance(&test_env.debt, &debt_pair) < XLM_ROUTE_DEBT_RESERVE);
+}
+
+#[test]
+fn test_exec_op_deleverage_splits_across_routes() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    let lender = Address::generate(env);
+    test_env.mint(&test_env.collateral, &test_env.leverage.address, 1_000 * SCALAR_7);
+    test_env.flash_loan(&lender, &test_env.collateral, 3_250 * SCALAR_7, 0);
+    test_env
+        .pool
+        .set_rates(&test_env.collateral, &1_100_000_000_000, &1_000_000_000_000);
+
+    let (route, debt_pair) = add_xlm_route(&test_env);
+    let direct = vec![env, test_env.collateral.clone(), test_env.debt.clone()];
+    test_env.leverage.set_delever_routes(&vec![env, direct, route]);
+    let pair_debt = test_env.balance(&test_env.debt, &test_env.pair);
+
+    test_env.flash_loan(&lender, &test_env.debt, 1_000 * SCALAR_7, 5 * SCALAR_7);
+
+    // Both legs traded and together bought just the repayment, leaving the
+    // unsold collateral to the owner
+    let event: DeleverageEvent = test_env.last_event(symbol_short!("delever"));
+    assert_eq!(event.swap_out, 1_005 * SCALAR_7);
+    assert!(test_env.balance(&test_env.debt, &test_env.pair) < pair_debt);
+    assert!(test_env.balance(&test_env.debt, &debt_pair) < XLM_ROUTE_DEBT_RESERVE);
+    assert_eq!(test_env.balance(&test_env.debt, &lender), 1_005 * SCALAR_7);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.owner), 0);
+    assert_eq!(test_env.balance(&test_env.debt, &test_env.leverage.address), 0);
+    assert_eq!(
+        test_env.balance(&test_env.collateral, &test_env.owner),
+        event.withdrawn - event.swap_in
+    );
+}
+
+#[test]
+fn test_rebalance_splits_keeper_repayment_across_routes() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &43_333, &Some(100), &None);
+    test_env.oracle.set_price(&test_env.collateral, &9_500_000);
+
+    let (route, debt_pair) = add_xlm_route(&test_env);
+    let direct = vec![env, test_env.collateral.clone(), test_env.debt.clone()];
+    test_env.leverage.set_delever_routes(&vec![env, direct, route]);
+    let pair_debt = test_env.balance(&test_env.debt, &test_env.pair);
+
+    let keeper = Address::generate(env);
+    test_env.mint(&test_env.debt, &keeper, 5_000 * SCALAR_7);
+    test_env.leverage.rebalance(&keeper);
+
+    // Both routes bought back the keeper's debt
+    assert_approx_eq_bps(test_env.c_factor(), TARGET_C_FACTOR, 50, "c-factor");
+    assert_eq!(test_env.balance(&test_env.debt, &keeper), 5_000 * SCALAR_7);
+    assert!(test_env.balance(&test_env.debt, &test_env.pair) < pair_debt);
+    assert!(test_env.balance(&test_env.debt, &debt_pair) < XLM_ROUTE_DEBT_RESERVE);
+}
+
+#[test]
+fn test_set_swap_paths_replaces_delever_routes() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let (route, debt_pair) = add_xlm_route(&test_env);
+    let direct = vec![env, test_env.collateral.clone(), test_env.debt.clone()];
+    test_env.leverage.set_delever_routes(&vec![env, direct.clone(), route]);
+    let lever = vec![env, test_env.debt.clone(), test_env.collateral.clone()];
+    test_env.leverage.set_swap_paths(&lever, &direct);
+
+    test_env
+        .leverage
+        .close_position(&0, &Some(MAX_SLIPPAGE_BPS), &None);
+
+    // Only the direct pair was sold into
+    assert_eq!(test_env.position(), (0, 0));
+    assert_eq!(test_env.balance(&test_env.debt, &debt_pair), XLM_ROUTE_DEBT_RESERVE);
+}
+
+#[test]
+fn test_set_delever_routes_must_join_the_assets() {
+    let test_env = setup_leverage();
+    let env = &test_env.env;
+    let invalid_config = Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)));
+
+    let result = test_env.leverage.try_set_delever_routes(&vec![env]);
+    assert_eq!(result.err(), invalid_config);
+
+    let routes = vec![
+        env,
+        vec![env, test_env.collateral.clone(), test_env.debt.clone()],
+        vec![env, test_env.debt.clone(), test_env.collateral.clone()],
+    ];
+    let result = test_env.leverage.try_set_delever_routes(&routes);
+    assert_eq!(result.err(), invalid_config);
+}
+
+/// Opens 10,000 collateral at 3x, returning how many swaps sold debt into
+/// the pair
+fn open_counting_swaps(test_env: &LeverageTestEnv) -> u32 {
+    test_env.mint(&test_env.collateral, &test_env.owner, 10_000 * SCALAR_7);
+    test_env
+        .leverage
+        .open_position(&(10_000 * SCALAR_7), &30_000, &Some(100), &None);
+
+    let env = &test_env.env;
+    let transfer = Symbol::new(env, "transfer");
+    env.events()
+        .all()
+        .iter()
+        .filter(|(contract, topics, _)| {
+            *contract == test_env.debt
+                && topics.get(0).and_then(|t| Symbol::try_from_val(env, &t).ok())
+                    == Some(transfer.clone())
+                && topics.get(2).and_then(|t| Address::try_from_val(env, &t).ok())
+                    == Some(test_env.pair.clone())
+        })
+        .count() as u32
+}
+
+#[test]
+fn test_max_price_impact_opens_in_more_rounds() {
+    let unlimited = setup_leverage();
+    let unlimited_swaps = open_counting_swaps(&unlimited);
+
+    // Rounds of ~8000 debt into the 10.5M debt reserve move the pair ~8 bps,
+    // so each is cut down to what moves it 5 bps
+    let test_env = setup_leverage();
+    test_env.leverage.set_max_price_impact(&5);
+    let swaps = open_counting_swaps(&test_env);
+
+    assert!(swaps > unlimited_swaps);
+    assert_approx_eq_bps(test_env.c_factor(), 15_000, 50, "c-factor");
+
+    // Closing is cut into rounds the same way
+    test_env
+        .leverage
+        .close_position(&0, &Some(MAX_SLIPPAGE_BPS), &None);
+    assert_eq!(test_env.position(), (0, 0));
+}
+
+#[test]
+fn test_max_price_impact_rejects_swaps_it_cannot_cut() {
+    let test_env = setup_leverage();
+    test_env.mint(&test_env.collateral, &test_env.owner, 1_000 * SCALAR_7);
+    test_env.leverage.set_max_price_impact(&0);
+
+    let result = test_env
+        .leverage
+        .try_open_position(&(1_000 * SCALAR_7), &30_000, &Some(100), &None);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::PriceImpactExceeded as u32)))
+    );
+}
+
+#[test]
+fn test_set_max_price_impact_outside_bps_fails() {
+    let test_env = setup_leverage();
+    let result = test_env.leverage.try_set_max_price_impact(&10_001);
+    assert_eq!(
+        result.err(),
+        Some(Ok(Error::from_contract_error(LeverageError::InvalidConfig as u32)))
+    );
+}